@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
@@ -33,6 +34,62 @@ impl Default for LogLevel {
     fn default() -> Self { LogLevel::Info }
 }
 
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq)]
+pub enum RunnerBackend {
+    Local,
+    Pbs,
+    Slurm,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq)]
+pub enum LocalSchedulingPolicy {
+    #[default]
+    Fifo,
+    FairShare,
+}
+
+/* Limits applied to every MMB process spawned by the Local backend */
+#[derive(Clone, Default, Deserialize)]
+pub struct LocalJobLimits {
+    /* CPU time in seconds */
+    #[serde(default)]
+    pub cpu_time: Option<u64>,
+    /* Resident set size in bytes */
+    #[serde(default)]
+    pub memory: Option<u64>,
+    #[serde(default)]
+    pub nice: Option<i32>,
+    #[serde(default)]
+    pub threads: Option<u32>,
+}
+
+/* Resources requested from PBS for a job */
+#[derive(Clone, Default, Deserialize)]
+pub struct PbsResources {
+    /* Walltime in seconds */
+    #[serde(default)]
+    pub walltime: Option<u64>,
+    #[serde(default)]
+    pub ncpus: Option<u32>,
+    /* Memory in bytes */
+    #[serde(default)]
+    pub mem: Option<u64>,
+}
+
+/* Profile used when no runner_profiles are configured. Its backend is derived
+ * from the runner_backend and use_pbs_offloading options. */
+pub const IMPLICIT_RUNNER_PROFILE: &str = "default";
+
+#[derive(Clone, Deserialize)]
+pub struct RunnerProfile {
+    pub backend: RunnerBackend,
+    #[serde(default)]
+    pub description: String,
+    /* PBS queue or Slurm partition the jobs are submitted to */
+    #[serde(default)]
+    pub queue: Option<String>,
+}
+
 #[derive(Clone, Deserialize)]
 pub struct Config {
     pub mmb_exec_path: String,
@@ -47,9 +104,43 @@ pub struct Config {
     #[serde(default = "oneshot_secret_key")]
     pub secret_key: String,
     pub require_https: bool,
+    #[serde(default)]
     pub use_pbs_offloading: bool,
+    #[serde(default)]
+    pub runner_backend: Option<RunnerBackend>,
+    #[serde(default)]
+    pub runner_profiles: HashMap<String, RunnerProfile>,
+    #[serde(default)]
+    pub default_runner_profile: Option<String>,
+    #[serde(default)]
+    pub max_local_jobs: Option<usize>,
+    #[serde(default)]
+    pub local_scheduling_policy: LocalSchedulingPolicy,
+    #[serde(default)]
+    pub local_job_limits: LocalJobLimits,
+    #[serde(default)]
+    pub pbs_default_resources: PbsResources,
+    #[serde(default)]
+    pub pbs_max_resources: PbsResources,
+    #[serde(default)]
+    pub pbs_allowed_queues: Option<Vec<String>>,
+    #[serde(default = "default_pbs_poll_interval")]
+    pub pbs_poll_interval: u64,
     pub verbose_rocket_logging: bool,
 
+    #[serde(default)]
+    pub session_idle_ttl: Option<u64>,
+    #[serde(default)]
+    pub session_max_age: Option<u64>,
+    #[serde(default)]
+    pub session_quota: Option<u64>,
+    #[serde(default)]
+    pub job_quota: Option<u64>,
+    #[serde(default)]
+    pub job_default_timeout: Option<u64>,
+    #[serde(default)]
+    pub job_max_timeout: Option<u64>,
+
     pub log_file: Option<String>,
     #[serde(default)]
     pub log_to_stdout: LogToStdOut,
@@ -70,7 +161,23 @@ lazy_static! {
                 port: 443,
                 require_https: true,
                 use_pbs_offloading: false,
+                runner_backend: None,
+                runner_profiles: HashMap::new(),
+                default_runner_profile: None,
+                max_local_jobs: None,
+                local_scheduling_policy: LocalSchedulingPolicy::default(),
+                local_job_limits: LocalJobLimits::default(),
+                pbs_default_resources: PbsResources::default(),
+                pbs_max_resources: PbsResources::default(),
+                pbs_allowed_queues: None,
+                pbs_poll_interval: default_pbs_poll_interval(),
                 verbose_rocket_logging: false,
+                session_idle_ttl: None,
+                session_max_age: None,
+                session_quota: None,
+                job_quota: None,
+                job_default_timeout: None,
+                job_max_timeout: None,
                 log_file: Some(String::from("/var/log/webmmb_server.log")),
                 log_to_stdout: LogToStdOut::default(),
                 log_level: LogLevel::default(),
@@ -79,6 +186,77 @@ lazy_static! {
     );
 }
 
+impl Config {
+    pub fn default_runner_profile(&self) -> String {
+        match &self.default_runner_profile {
+            Some(name) => name.clone(),
+            None => String::from(IMPLICIT_RUNNER_PROFILE),
+        }
+    }
+
+    /* Explicitly configured backend takes precedence over the legacy use_pbs_offloading switch */
+    pub fn runner_backend(&self) -> RunnerBackend {
+        match self.runner_backend {
+            Some(backend) => backend,
+            None => match self.use_pbs_offloading {
+                true => RunnerBackend::Pbs,
+                false => RunnerBackend::Local,
+            },
+        }
+    }
+
+    pub fn runner_profile(&self, name: &str) -> Option<RunnerProfile> {
+        self.runner_profiles().remove(name)
+    }
+
+    pub fn runner_profiles(&self) -> HashMap<String, RunnerProfile> {
+        if !self.runner_profiles.is_empty() {
+            return self.runner_profiles.clone();
+        }
+
+        let mut profiles = HashMap::new();
+        profiles.insert(
+            String::from(IMPLICIT_RUNNER_PROFILE),
+            RunnerProfile{
+                backend: self.runner_backend(),
+                description: String::new(),
+                queue: None,
+            }
+        );
+        profiles
+    }
+}
+
+impl PbsResources {
+    /* Checks that the requested resources do not exceed these */
+    pub fn check_request(&self, rq: &PbsResources) -> Result<(), String> {
+        fn check(name: &str, rq: Option<u64>, max: Option<u64>) -> Result<(), String> {
+            match (rq, max) {
+                (Some(0), _) => Err(format!("Requested {} cannot be zero", name)),
+                (Some(rq), Some(max)) if rq > max => Err(format!("Requested {} {} exceeds the maximum of {}", name, rq, max)),
+                _ => Ok(()),
+            }
+        }
+
+        check("walltime", rq.walltime, self.walltime)?;
+        check("ncpus", rq.ncpus.map(u64::from), self.ncpus.map(u64::from))?;
+        check("mem", rq.mem, self.mem)
+    }
+
+    /* Requested resources take precedence over these */
+    pub fn merged(&self, rq: &PbsResources) -> PbsResources {
+        PbsResources{
+            walltime: rq.walltime.or(self.walltime),
+            ncpus: rq.ncpus.or(self.ncpus),
+            mem: rq.mem.or(self.mem),
+        }
+    }
+}
+
+fn default_pbs_poll_interval() -> u64 {
+    10
+}
+
 fn oneshot_secret_key() -> String {
     let mut rng = rand::thread_rng();
     let mut random_blob = Vec::from(rng.gen::<[u8; 32]>());
@@ -133,6 +311,62 @@ pub fn load(cfg_path: PathBuf) {
         log_plain!(Critical, LOGSRC, "Invalid configuration - port number cannot be zero");
         panic!();
     }
+    if cfg.session_idle_ttl == Some(0) || cfg.session_max_age == Some(0) {
+        log_plain!(Critical, LOGSRC, "Invalid configuration - session lifetime limits cannot be zero");
+        panic!();
+    }
+
+    if cfg.job_default_timeout == Some(0) || cfg.job_max_timeout == Some(0) {
+        log_plain!(Critical, LOGSRC, "Invalid configuration - job timeouts cannot be zero");
+        panic!();
+    }
+    if let (Some(default), Some(max)) = (cfg.job_default_timeout, cfg.job_max_timeout) {
+        if default > max {
+            log_plain!(Critical, LOGSRC, "Invalid configuration - default job timeout exceeds the maximum job timeout");
+            panic!();
+        }
+    }
+
+    if cfg.max_local_jobs == Some(0) {
+        log_plain!(Critical, LOGSRC, "Invalid configuration - maximum number of local jobs cannot be zero");
+        panic!();
+    }
+
+    if cfg.local_job_limits.cpu_time == Some(0) || cfg.local_job_limits.memory == Some(0) || cfg.local_job_limits.threads == Some(0) {
+        log_plain!(Critical, LOGSRC, "Invalid configuration - local job limits cannot be zero");
+        panic!();
+    }
+    if let Some(nice) = cfg.local_job_limits.nice {
+        if !(-20..=19).contains(&nice) {
+            log_plain!(Critical, LOGSRC, "Invalid configuration - nice level of local jobs must be between -20 and 19");
+            panic!();
+        }
+    }
+
+    if cfg.pbs_poll_interval == 0 {
+        log_plain!(Critical, LOGSRC, "Invalid configuration - PBS poll interval cannot be zero");
+        panic!();
+    }
+    if let Err(e) = cfg.pbs_max_resources.check_request(&cfg.pbs_default_resources) {
+        log_plain!(Critical, LOGSRC, &format!("Invalid configuration - default PBS resources exceed the maximum: {}", e));
+        panic!();
+    }
+
+    if !cfg.runner_profiles.is_empty() {
+        match &cfg.default_runner_profile {
+            Some(name) => if !cfg.runner_profiles.contains_key(name) {
+                log_plain!(Critical, LOGSRC, &format!("Invalid configuration - default runner profile {} is not defined", name));
+                panic!();
+            },
+            None => {
+                log_plain!(Critical, LOGSRC, "Invalid configuration - default_runner_profile must be set when runner_profiles are defined");
+                panic!();
+            },
+        }
+    } else if cfg.default_runner_profile.is_some() {
+        log_plain!(Critical, LOGSRC, "Invalid configuration - default_runner_profile is set but no runner_profiles are defined");
+        panic!();
+    }
 
     *CONFIG.write().unwrap() = Arc::new(cfg);
 }
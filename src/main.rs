@@ -11,6 +11,7 @@ mod mmb;
 mod pbs;
 mod server;
 mod session;
+mod slurm;
 
 const LOGSRC: &'static str = "main";
 
@@ -49,6 +50,20 @@ fn check_and_prepare() {
         panic!();
     }
 
+    /* MMB reads the parameters file on its own, the server only uses it to check commands */
+    match mmb::parameters::load(Path::new(&cfg.mmb_parameters_path)) {
+        Ok(()) => {
+            let classes = mmb::parameters::get();
+            if classes.ntcs.is_empty() {
+                log_plain!(Warning, LOGSRC, "MMB parameters file defines no NtC classes, NtCs in commands will not be checked");
+            }
+            if classes.edges.is_empty() {
+                log_plain!(Warning, LOGSRC, "MMB parameters file defines no base interactions, base interactions in commands will not be checked");
+            }
+        },
+        Err(e) => log_plain!(Error, LOGSRC, &format!("Failed to read MMB parameters file, NtCs and base interactions in commands will not be checked: {}", e)),
+    };
+
     let p = Path::new(cfg.jobs_dir.as_str());
     if !Path::is_dir(p) {
         let mut db = std::fs::DirBuilder::new();
@@ -1,10 +1,14 @@
 use crate::mmb;
+use crate::session;
 
 const RESERVED_FILE_NAMES: &'static[&'static str] = &[
     mmb::CMDS_FILE_NAME,
     mmb::DOUT_FILE_NAME,
     mmb::PGRS_FILE_NAME,
     mmb::PARAMS_FILE_NAME,
+    session::JOB_MANIFEST_FILE_NAME,
+    session::STDERR_FILE_NAME,
+    session::STDOUT_FILE_NAME,
     "frame.pdb",
 ];
 
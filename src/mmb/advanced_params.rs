@@ -1,36 +1,274 @@
 use serde_json;
 
+use crate::mmb::ast::{Command, Parameter};
+pub use crate::mmb::vocabulary::ArgType;
+use crate::mmb::vocabulary::CommandSpec;
 use crate::server::api;
 
-fn value_to_string(value: serde_json::Value) -> Result<String, serde_json::Error> {
-    match serde_json::from_value::<i32>(value.clone()) {
-        Ok(v) => return Ok(i32::to_string(&v)),
-        Err(_) => (),
+#[derive(Clone, Copy)]
+pub enum ParamValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(&'static str),
+}
+
+/* Parameter of the simulation that synthetic commands may set. This table is also
+ * what the raw commands are checked against. Range limits are inclusive and apply
+ * only to numeric parameters. Parameters without a default are left to MMB. */
+pub struct ParamSpec {
+    pub command: CommandSpec,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub default: Option<ParamValue>,
+    pub description: &'static str,
+}
+
+impl ParamSpec {
+    pub fn name(&self) -> &'static str {
+        self.command.name
     }
 
-    match serde_json::from_value::<f64>(value.clone()) {
-        Ok(v) => return Ok(f64::to_string(&v).replace(",", ".")),
-        Err(_) => (),
+    pub fn value_type(&self) -> ArgType {
+        self.command.args[0]
     }
+}
+
+const BOOL: &[ArgType] = &[ArgType::Bool];
+const FLOAT: &[ArgType] = &[ArgType::Float];
+const INT: &[ArgType] = &[ArgType::Int];
+const WORD: &[ArgType] = &[ArgType::Word];
+const THERMOSTAT_TYPE: &[ArgType] = &[ArgType::OneOf(&["NoseHoover", "VelocityRescaling"])];
+
+const fn param(name: &'static str, args: &'static [ArgType], min: Option<f64>, max: Option<f64>, default: Option<ParamValue>, description: &'static str) -> ParamSpec {
+    ParamSpec{
+        command: CommandSpec{ name, args, required: 1, arities: &[], variadic: false },
+        min,
+        max,
+        default,
+        description,
+    }
+}
 
-    match serde_json::from_value::<bool>(value.clone()) {
-        Ok(v) => match v {
-            true => return Ok(String::from("True")),
-            false => return Ok(String::from("False")),
+const fn flag(name: &'static str, default: Option<bool>, description: &'static str) -> ParamSpec {
+    let default = match default {
+        Some(v) => Some(ParamValue::Bool(v)),
+        None => None,
+    };
+    param(name, BOOL, None, None, default, description)
+}
+
+const fn non_negative(name: &'static str, description: &'static str) -> ParamSpec {
+    param(name, FLOAT, Some(0.0), None, None, description)
+}
+
+const fn scale_factor(name: &'static str, description: &'static str) -> ParamSpec {
+    param(name, FLOAT, Some(0.0), None, Some(ParamValue::Float(0.0)), description)
+}
+
+static SCHEMA: &[ParamSpec] = &[
+    flag("addAllAtomSterics", None, "Add steric repulsion between all atoms"),
+    flag("addAllHeavyAtomSterics", None, "Add steric repulsion between all heavy atoms"),
+    flag("addProteinBackboneSterics", None, "Add steric repulsion between protein backbone atoms"),
+    flag("addRNABackboneSterics", None, "Add steric repulsion between RNA backbone atoms"),
+    flag("calcEnergy", None, "Calculate and report the energy of the system"),
+    flag("checkSatisfied", None, "Stop the stage early once all base pairs are satisfied"),
+    non_negative("cutoffRadius", "Cutoff radius of the nonbonded interactions in nm"),
+    non_negative("densityForceConstant", "Strength of the force that pulls atoms into the density map"),
+    param("dutyCycle", FLOAT, Some(0.0), Some(1.0), None, "Fraction of reporting intervals in which base pair interactions are active"),
+    non_negative("excludedVolumeRadius", "Radius of the excluded volume spheres in nm"),
+    non_negative("excludedVolumeStiffness", "Stiffness of the excluded volume contacts"),
+    scale_factor("globalAmberImproperTorsionScaleFactor", "Scales the improper torsion terms of the Amber force field"),
+    scale_factor("globalBondBendScaleFactor", "Scales the bond bending terms of the force field"),
+    scale_factor("globalBondStretchScaleFactor", "Scales the bond stretching terms of the force field"),
+    scale_factor("globalBondTorsionScaleFactor", "Scales the bond torsion terms of the force field"),
+    scale_factor("globalCoulombScaleFactor", "Scales the electrostatic interactions"),
+    scale_factor("globalGbsaScaleFactor", "Scales the generalized Born implicit solvent term"),
+    scale_factor("globalVdwScaleFactor", "Scales the van der Waals interactions"),
+    flag("guessCoordinates", None, "Guess coordinates of atoms missing from the input structure"),
+    non_negative("initialSeparation", "Initial separation of the chains in nm"),
+    non_negative("integratorAccuracy", "Accuracy of variable step size integrators"),
+    non_negative("integratorStepSize", "Step size of fixed step size integrators in ps"),
+    param("integratorType", WORD, None, None, None, "Integrator used to advance the simulation"),
+    scale_factor("kbBackboneTorsionGlobalScaleFactor", "Scales the knowledge-based backbone torsion potential"),
+    flag("matchExact", None, "Match the internal coordinates of the model exactly to the input structure"),
+    flag("matchHydrogenAtomLocations", None, "Match the locations of hydrogen atoms to the input structure"),
+    flag("matchOptimize", None, "Optimize the match of the model to the input structure"),
+    flag("matchPurineN1AtomLocations", None, "Match the locations of purine N1 atoms to the input structure"),
+    flag("minimize", Some(false), "Minimize energy instead of running dynamics"),
+    non_negative("monteCarloTemperature", "Temperature of the Monte Carlo sampling in K"),
+    scale_factor("nastGlobalBondTorsionScaleFactor", "Scales the bond torsion terms of the NAST coarse-grained model"),
+    param(
+        "physicsRadius",
+        FLOAT,
+        Some(0.0),
+        None,
+        Some(ParamValue::Float(0.0)),
+        "Atoms within this distance in nm of flexible residues are treated with the full force field"
+    ),
+    flag("piecewiseRigidify", None, "Rigidify segments of the structure that are not flexible"),
+    non_negative("planarityThreshold", "Threshold of the base planarity check"),
+    param("potentialType", WORD, None, None, None, "Shape of the potential of the contacts"),
+    flag("proteinCapping", None, "Add terminal caps to protein chains"),
+    flag("removeRigidBodyMomentum", None, "Remove the rigid body momentum of the system"),
+    flag("rigidifyFormedHelices", None, "Rigidify helices whose base pairs are all satisfied"),
+    flag("setChiBondAntiStacking", None, "Set the glycosidic bonds to the anti conformation"),
+    flag("setHelicalStacking", Some(true), "Add stacking interactions between consecutive base pairs of double helices"),
+    flag("setRepulsiveForce", None, "Add repulsive forces between chains"),
+    non_negative("smallGroupInertiaMultiplier", "Multiplies the inertia of small rigid groups"),
+    flag("stackAllHelicalResidues", None, "Add stacking interactions between all helical residues"),
+    param("thermostatType", THERMOSTAT_TYPE, None, None, Some(ParamValue::Text("NoseHoover")), "Thermostat that keeps the temperature of the system"),
+    non_negative("twoTransformForceMultiplier", "Multiplies the strength of the base pair forces"),
+    flag("useFixedStepSize", None, "Use an integrator with a fixed step size"),
+    flag("useMultithreadedComputation", Some(true), "Use multiple threads for the computation"),
+    flag("useOpenMMAcceleration", Some(false), "Compute nonbonded forces with OpenMM"),
+    non_negative("vanderWallSphereRadius", "Radius of the van der Waals spheres in nm"),
+    param(
+        "velocityRescalingInterval",
+        INT,
+        Some(1.0),
+        None,
+        Some(ParamValue::Int(3500)),
+        "Number of time steps between velocity rescalings when the VelocityRescaling thermostat is used"
+    ),
+    flag("verbose", None, "Write detailed output"),
+    flag("writeDoublehelixCommands", None, "Write the commands of the double helices that were detected"),
+    flag("writeLastFrameFile", None, "Write the last frame of each stage to a separate file"),
+];
+
+fn check_range(spec: &ParamSpec, v: f64) -> Result<(), String> {
+    if let Some(min) = spec.min {
+        if v < min {
+            return Err(format!("Advanced parameter {} cannot be lower than {}", spec.name(), min));
+        }
+    }
+    if let Some(max) = spec.max {
+        if v > max {
+            return Err(format!("Advanced parameter {} cannot be higher than {}", spec.name(), max));
         }
-        Err(_) => (),
     }
+    Ok(())
+}
+
+fn value_to_string(spec: &ParamSpec, value: &serde_json::Value) -> Result<String, String> {
+    let invalid = || format!("Advanced parameter {} must be {}", spec.name(), spec.value_type());
+
+    match spec.value_type() {
+        ArgType::Bool => match value.as_bool() {
+            Some(true) => Ok(String::from("True")),
+            Some(false) => Ok(String::from("False")),
+            None => Err(invalid()),
+        },
+        ArgType::Int => match value.as_i64() {
+            Some(v) => {
+                check_range(spec, v as f64)?;
+                Ok(v.to_string())
+            },
+            None => Err(invalid()),
+        },
+        ArgType::Float => match value.as_f64() {
+            Some(v) => {
+                check_range(spec, v)?;
+                Ok(v.to_string())
+            },
+            None => Err(invalid()),
+        },
+        ArgType::OneOf(choices) => match value.as_str() {
+            Some(v) => match choices.contains(&v) {
+                true => Ok(String::from(v)),
+                false => Err(invalid()),
+            },
+            None => Err(invalid()),
+        },
+        value_type => match value.as_str() {
+            Some(v) if !v.is_empty() && !v.contains(char::is_whitespace) => match value_type.check(v) {
+                None => Ok(String::from(v)),
+                Some(e) => Err(format!("Advanced parameter {}: {}", spec.name(), e)),
+            },
+            _ => Err(invalid()),
+        },
+    }
+}
 
-    serde_json::from_value::<String>(value)
+pub fn lookup(name: &str) -> Option<&'static ParamSpec> {
+    SCHEMA.iter().find(|spec| spec.name() == name)
 }
 
-pub fn to_txt(params: &api::JsonAdvancedParameters) -> Result<String, serde_json::Error> {
-    let mut txt = String::new();
+pub fn schema() -> &'static [ParamSpec] {
+    SCHEMA
+}
+
+pub fn to_commands(params: &api::JsonAdvancedParameters) -> Result<Vec<Command>, String> {
+    let mut cmds = Vec::new();
     for (k, v) in params.iter() {
-        match value_to_string(v.clone()) {
-            Ok(s) => txt.push_str(format!("{} {}\n", k, s).as_str()),
-            Err(e) => return Err(e),
+        let spec = match lookup(k) {
+            Some(spec) => spec,
+            None => return Err(format!("Unknown advanced parameter {}", k)),
+        };
+        let value = value_to_string(spec, v)?;
+        match Parameter::new(k, &value) {
+            Some(p) => cmds.push(Command::Parameter(p)),
+            None => return Err(format!("Advanced parameter {} cannot be set to {}", k, value)),
+        };
+    }
+    Ok(cmds)
+}
+
+/* Reads the value of an advanced parameter as it appears in raw commands */
+pub fn value_from_raw(name: &str, text: &str) -> Result<serde_json::Value, String> {
+    let spec = match lookup(name) {
+        Some(spec) => spec,
+        None => return Err(format!("Unknown advanced parameter {}", name)),
+    };
+
+    let value = match spec.value_type() {
+        ArgType::Bool => {
+            if text.eq_ignore_ascii_case("true") || text == "1" {
+                serde_json::Value::Bool(true)
+            } else if text.eq_ignore_ascii_case("false") || text == "0" {
+                serde_json::Value::Bool(false)
+            } else {
+                serde_json::Value::Null
+            }
+        },
+        ArgType::Int => text.parse::<i64>().map_or(serde_json::Value::Null, serde_json::Value::from),
+        ArgType::Float => text.parse::<f64>().map_or(serde_json::Value::Null, serde_json::Value::from),
+        /* MMB does not care about the case of the choices, the schema lists them in one spelling */
+        ArgType::OneOf(choices) => {
+            let choice = choices.iter().find(|c| c.eq_ignore_ascii_case(text)).copied().unwrap_or(text);
+            serde_json::Value::String(String::from(choice))
+        },
+        _ => serde_json::Value::String(String::from(text)),
+    };
+
+    value_to_string(spec, &value)?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid_values() {
+        for spec in schema() {
+            let value = match spec.default {
+                Some(ParamValue::Bool(v)) => serde_json::Value::from(v),
+                Some(ParamValue::Int(v)) => serde_json::Value::from(v),
+                Some(ParamValue::Float(v)) => serde_json::Value::from(v),
+                Some(ParamValue::Text(v)) => serde_json::Value::from(v),
+                None => continue,
+            };
+            let text = value_to_string(spec, &value).unwrap();
+            assert!(Parameter::new(spec.name(), &text).is_some(), "Default of {} does not read back", spec.name());
         }
     }
-    Ok(txt)
+
+    #[test]
+    fn raw_values_are_read_with_the_schema() {
+        assert_eq!(value_from_raw("thermostatType", "velocityrescaling").unwrap(), serde_json::Value::from("VelocityRescaling"));
+        assert_eq!(value_from_raw("useFixedStepSize", "1").unwrap(), serde_json::Value::from(true));
+        assert!(value_from_raw("thermostatType", "Berendsen").is_err());
+        assert!(value_from_raw("integratorStepSize", "-0.001").is_err());
+    }
 }
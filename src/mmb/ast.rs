@@ -0,0 +1,509 @@
+/* Typed representation of MMB commands. Commands are turned into text only by
+ * the Display implementation and text is turned into commands only by "parse",
+ * so that anything written can be read back as the same commands. Arguments
+ * a command accepts are defined once, by the vocabulary. */
+
+use std::fmt;
+
+use crate::mmb::vocabulary;
+
+const COMMENT_START: char = '#';
+pub const VARIABLE_PREFIX: char = '@';
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MoleculeType {
+    Dna,
+    Protein,
+    Rna,
+}
+
+/* Stages for which MMB reads the commands of a block */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StageCondition {
+    /* Only the given stage */
+    At,
+    /* The given stage and all later stages */
+    From,
+    /* All stages up to and including the given stage */
+    To,
+}
+
+/* Part of the molecule a mobilizer applies to */
+#[derive(Clone, Debug, PartialEq)]
+pub enum MobilizerScope {
+    All,
+    Chain(String),
+    Residues{
+        chain: String,
+        first: i32,
+        last: i32,
+    },
+}
+
+/* Command that sets a parameter which has no typed command. It can be created only
+ * for parameters known to the vocabulary so that it never stands in for a typed command. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameter {
+    name: String,
+    value: String,
+}
+
+/* Command that has no typed representation, including those that use variables.
+ * Such commands come only from parsing. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Untyped {
+    name: String,
+    args: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    FirstStage(i32),
+    LastStage(i32),
+    ReadBlockStart{
+        condition: StageCondition,
+        stage: i32,
+    },
+    ReadBlockEnd,
+    ReportingInterval(f64),
+    NumReportingIntervals(i32),
+    BaseInteractionScaleFactor(f64),
+    Temperature(f64),
+    SetDefaultMDParameters,
+    Sequence{
+        molecule: MoleculeType,
+        chain: String,
+        first_residue: i32,
+        sequence: String,
+    },
+    NucleicAcidDuplex{
+        chain_1: String,
+        first_residue_1: i32,
+        last_residue_1: i32,
+        chain_2: String,
+        first_residue_2: i32,
+        last_residue_2: i32,
+    },
+    BaseInteraction{
+        chain_1: String,
+        residue_1: i32,
+        edge_1: String,
+        chain_2: String,
+        residue_2: i32,
+        edge_2: String,
+        orientation: String,
+    },
+    NtC{
+        chain: String,
+        first_residue: i32,
+        last_residue: i32,
+        ntc: String,
+        weight: Option<f64>,
+    },
+    NtCForceScaleFactor(f64),
+    Mobilizer{
+        bond_mobility: String,
+        scope: MobilizerScope,
+    },
+    LoadSequencesFromPdb(Option<String>),
+    DensityFileName(String),
+    FitToDensity,
+    Parameter(Parameter),
+    Other(Untyped),
+}
+
+pub struct Token<'a> {
+    pub text: &'a str,
+    /* Column of the first character, counted from 1 */
+    pub column: usize,
+}
+
+pub struct ParsedCommand<'a> {
+    /* Line number, counted from 1 */
+    pub line: usize,
+    pub text: &'a str,
+    pub tokens: Vec<Token<'a>>,
+    pub command: Command,
+}
+
+impl StageCondition {
+    pub fn from_name(name: &str) -> Option<StageCondition> {
+        match name.to_lowercase().as_str() {
+            "readatstage" => Some(StageCondition::At),
+            "readfromstage" => Some(StageCondition::From),
+            "readtostage" => Some(StageCondition::To),
+            _ => None,
+        }
+    }
+
+    /* Whether a block opened for "block_stage" is read when MMB runs "stage" */
+    pub fn applies(&self, block_stage: i32, stage: i32) -> bool {
+        match self {
+            StageCondition::At => stage == block_stage,
+            StageCondition::From => stage >= block_stage,
+            StageCondition::To => stage <= block_stage,
+        }
+    }
+}
+
+impl Parameter {
+    pub fn new(name: &str, value: &str) -> Option<Parameter> {
+        match vocabulary::lookup_parameter(name) {
+            Some(spec) => match spec.accepts_args(&[value]) {
+                true => Some(Parameter{ name: String::from(name), value: String::from(value) }),
+                false => None,
+            },
+            None => None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl Untyped {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn uses_variables(&self) -> bool {
+        self.name.starts_with(VARIABLE_PREFIX) || self.args.iter().any(|a| a.starts_with(VARIABLE_PREFIX))
+    }
+}
+
+impl fmt::Display for StageCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StageCondition::At => write!(f, "readAtStage"),
+            StageCondition::From => write!(f, "readFromStage"),
+            StageCondition::To => write!(f, "readToStage"),
+        }
+    }
+}
+
+impl fmt::Display for MoleculeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoleculeType::Dna => write!(f, "DNA"),
+            MoleculeType::Protein => write!(f, "Protein"),
+            MoleculeType::Rna => write!(f, "RNA"),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::FirstStage(v) => write!(f, "firstStage {}", v),
+            Command::LastStage(v) => write!(f, "lastStage {}", v),
+            Command::ReadBlockStart{ condition, stage } => write!(f, "{} {}", condition, stage),
+            Command::ReadBlockEnd => write!(f, "readBlockEnd"),
+            Command::ReportingInterval(v) => write!(f, "reportingInterval {}", v),
+            Command::NumReportingIntervals(v) => write!(f, "numReportingIntervals {}", v),
+            Command::BaseInteractionScaleFactor(v) => write!(f, "baseInteractionScaleFactor {}", v),
+            Command::Temperature(v) => write!(f, "temperature {}", v),
+            Command::SetDefaultMDParameters => write!(f, "setDefaultMDParameters"),
+            Command::Sequence{ molecule, chain, first_residue, sequence } => write!(f, "{} {} {} {}", molecule, chain, first_residue, sequence),
+            Command::NucleicAcidDuplex{ chain_1, first_residue_1, last_residue_1, chain_2, first_residue_2, last_residue_2 } => write!(
+                f, "nucleicAcidDuplex {} {} {} {} {} {}",
+                chain_1, first_residue_1, last_residue_1,
+                chain_2, first_residue_2, last_residue_2
+            ),
+            Command::BaseInteraction{ chain_1, residue_1, edge_1, chain_2, residue_2, edge_2, orientation } => write!(
+                f, "baseInteraction {} {} {} {} {} {} {}",
+                chain_1, residue_1, edge_1,
+                chain_2, residue_2, edge_2,
+                orientation
+            ),
+            Command::NtC{ chain, first_residue, last_residue, ntc, weight } => {
+                write!(f, "NtC {} {} {} {}", chain, first_residue, last_residue, ntc)?;
+                match weight {
+                    Some(w) => write!(f, " {}", w),
+                    None => Ok(()),
+                }
+            },
+            Command::NtCForceScaleFactor(v) => write!(f, "NtCForceScaleFactor {}", v),
+            Command::Mobilizer{ bond_mobility, scope } => match scope {
+                MobilizerScope::All => write!(f, "mobilizer {}", bond_mobility),
+                MobilizerScope::Chain(chain) => write!(f, "mobilizer {} {}", bond_mobility, chain),
+                MobilizerScope::Residues{ chain, first, last } => write!(f, "mobilizer {} {} {} {}", bond_mobility, chain, first, last),
+            },
+            Command::LoadSequencesFromPdb(file) => match file {
+                Some(name) => write!(f, "loadSequencesFromPdb {}", name),
+                None => write!(f, "loadSequencesFromPdb"),
+            },
+            Command::DensityFileName(v) => write!(f, "density densityFileName {}", v),
+            Command::FitToDensity => write!(f, "fitToDensity"),
+            Command::Parameter(p) => write!(f, "{} {}", p.name, p.value),
+            Command::Other(u) => {
+                write!(f, "{}", u.name)?;
+                for a in u.args.iter() {
+                    write!(f, " {}", a)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+/* Reads arguments that the vocabulary accepts for the command as a typed command */
+fn parse_command(name: &str, args: &[&str]) -> Option<Command> {
+    let spec = vocabulary::lookup(name)?;
+    if !spec.accepts_args(args) {
+        return None;
+    }
+
+    let int = |idx: usize| args[idx].parse::<i32>().ok();
+    let float = |idx: usize| args[idx].parse::<f64>().ok();
+    let word = |idx: usize| String::from(args[idx]);
+
+    let cmd = match spec.name {
+        "firstStage" => Command::FirstStage(int(0)?),
+        "lastStage" => Command::LastStage(int(0)?),
+        "readAtStage" | "readFromStage" | "readToStage" => Command::ReadBlockStart{
+            condition: StageCondition::from_name(name)?,
+            stage: int(0)?,
+        },
+        "readBlockEnd" => Command::ReadBlockEnd,
+        "reportingInterval" => Command::ReportingInterval(float(0)?),
+        "numReportingIntervals" => Command::NumReportingIntervals(int(0)?),
+        "baseInteractionScaleFactor" => Command::BaseInteractionScaleFactor(float(0)?),
+        "temperature" => Command::Temperature(float(0)?),
+        "setDefaultMDParameters" => Command::SetDefaultMDParameters,
+        "DNA" | "Protein" | "RNA" => Command::Sequence{
+            molecule: match spec.name {
+                "DNA" => MoleculeType::Dna,
+                "Protein" => MoleculeType::Protein,
+                _ => MoleculeType::Rna,
+            },
+            chain: word(0),
+            first_residue: int(1)?,
+            sequence: word(2),
+        },
+        "nucleicAcidDuplex" => Command::NucleicAcidDuplex{
+            chain_1: word(0),
+            first_residue_1: int(1)?,
+            last_residue_1: int(2)?,
+            chain_2: word(3),
+            first_residue_2: int(4)?,
+            last_residue_2: int(5)?,
+        },
+        "baseInteraction" => Command::BaseInteraction{
+            chain_1: word(0),
+            residue_1: int(1)?,
+            edge_1: word(2),
+            chain_2: word(3),
+            residue_2: int(4)?,
+            edge_2: word(5),
+            orientation: word(6),
+        },
+        "NtC" => Command::NtC{
+            chain: word(0),
+            first_residue: int(1)?,
+            last_residue: int(2)?,
+            ntc: word(3),
+            weight: match args.len() {
+                5 => Some(float(4)?),
+                _ => None,
+            },
+        },
+        "NtCForceScaleFactor" => Command::NtCForceScaleFactor(float(0)?),
+        "mobilizer" => Command::Mobilizer{
+            bond_mobility: word(0),
+            scope: match args.len() {
+                1 => MobilizerScope::All,
+                2 => MobilizerScope::Chain(word(1)),
+                _ => MobilizerScope::Residues{ chain: word(1), first: int(2)?, last: int(3)? },
+            },
+        },
+        "loadSequencesFromPdb" => Command::LoadSequencesFromPdb(args.first().map(|a| String::from(*a))),
+        "density" if args[0] == "densityFileName" => Command::DensityFileName(word(1)),
+        "fitToDensity" => Command::FitToDensity,
+        _ => match args.len() {
+            1 => Command::Parameter(Parameter::new(name, args[0])?),
+            _ => return None,
+        },
+    };
+
+    Some(cmd)
+}
+
+fn parse_line(idx: usize, text: &str) -> Option<ParsedCommand<'_>> {
+    let tokens = tokenize(text);
+    if tokens.is_empty() {
+        return None;
+    }
+
+    let name = tokens[0].text;
+    let args = tokens[1..].iter().map(|t| t.text).collect::<Vec<_>>();
+    let uses_variables = tokens.iter().any(|t| t.text.starts_with(VARIABLE_PREFIX));
+    let command = match uses_variables {
+        false => parse_command(name, &args),
+        true => None,
+    }.unwrap_or_else(|| Command::Other(Untyped{ name: String::from(name), args: args.iter().map(|a| String::from(*a)).collect() }));
+
+    Some(ParsedCommand{ line: idx + 1, text, tokens, command })
+}
+
+/* Commands that cannot be read as any of the typed commands are kept as they are */
+pub fn parse(raw: &str) -> Vec<ParsedCommand<'_>> {
+    raw.split('\n').enumerate().filter_map(|(idx, text)| parse_line(idx, text)).collect()
+}
+
+/* Splits a line of raw commands into words, leaving out comments */
+pub fn tokenize(line: &str) -> Vec<Token<'_>> {
+    let content = match line.find(COMMENT_START) {
+        Some(idx) => &line[..idx],
+        None => line,
+    };
+
+    let mut tokens = Vec::new();
+    let mut start: Option<(usize, usize)> = None;
+    let mut column = 0;
+    for (idx, c) in content.char_indices() {
+        column += 1;
+        if c.is_whitespace() {
+            if let Some((s, col)) = start.take() {
+                tokens.push(Token{ text: &content[s..idx], column: col });
+            }
+        } else if start.is_none() {
+            start = Some((idx, column));
+        }
+    }
+    if let Some((s, col)) = start {
+        tokens.push(Token{ text: &content[s..], column: col });
+    }
+
+    tokens
+}
+
+/* Commands whose text would be read back as something else, such as names that
+ * contain whitespace or the comment character, are refused */
+pub fn to_txt(commands: &[Command]) -> Result<String, String> {
+    let mut txt = String::new();
+    for cmd in commands.iter() {
+        let line = cmd.to_string();
+        match parse_line(0, &line) {
+            Some(parsed) if parsed.command == *cmd => (),
+            _ => return Err(format!("Command \"{}\" cannot be written as an MMB command", line)),
+        };
+        txt += format!("{}\n", line).as_str();
+    }
+    Ok(txt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_commands() -> Vec<Command> {
+        vec![
+            Command::FirstStage(1),
+            Command::LastStage(3),
+            Command::ReadBlockStart{ condition: StageCondition::At, stage: 1 },
+            Command::ReadBlockStart{ condition: StageCondition::From, stage: 2 },
+            Command::ReadBlockStart{ condition: StageCondition::To, stage: 3 },
+            Command::ReadBlockEnd,
+            Command::ReportingInterval(0.1),
+            Command::NumReportingIntervals(200),
+            Command::BaseInteractionScaleFactor(200.0),
+            Command::Temperature(-12.5e-3),
+            Command::SetDefaultMDParameters,
+            Command::Sequence{ molecule: MoleculeType::Dna, chain: String::from("A"), first_residue: -4, sequence: String::from("ACGT") },
+            Command::Sequence{ molecule: MoleculeType::Protein, chain: String::from("P"), first_residue: 1, sequence: String::from("MKV") },
+            Command::Sequence{ molecule: MoleculeType::Rna, chain: String::from("B"), first_residue: i32::MAX, sequence: String::from("G") },
+            Command::NucleicAcidDuplex{
+                chain_1: String::from("A"), first_residue_1: 1, last_residue_1: 4,
+                chain_2: String::from("B"), first_residue_2: 8, last_residue_2: 5,
+            },
+            Command::BaseInteraction{
+                chain_1: String::from("A"), residue_1: 1, edge_1: String::from("WatsonCrick"),
+                chain_2: String::from("B"), residue_2: i32::MIN, edge_2: String::from("Hoogsteen"),
+                orientation: String::from("Cis"),
+            },
+            Command::NtC{ chain: String::from("A"), first_residue: 1, last_residue: 2, ntc: String::from("AA00"), weight: None },
+            Command::NtC{ chain: String::from("A"), first_residue: 2, last_residue: 3, ntc: String::from("BB01"), weight: Some(0.25) },
+            Command::NtCForceScaleFactor(1.0 / 3.0),
+            Command::Mobilizer{ bond_mobility: String::from("Rigid"), scope: MobilizerScope::All },
+            Command::Mobilizer{ bond_mobility: String::from("Torsion"), scope: MobilizerScope::Chain(String::from("A")) },
+            Command::Mobilizer{ bond_mobility: String::from("Free"), scope: MobilizerScope::Residues{ chain: String::from("B"), first: 3, last: 7 } },
+            Command::LoadSequencesFromPdb(None),
+            Command::LoadSequencesFromPdb(Some(String::from("structure.pdb"))),
+            Command::DensityFileName(String::from("map.mrc")),
+            Command::FitToDensity,
+            Command::Parameter(Parameter::new("physicsRadius", "0.4").unwrap()),
+            Command::Parameter(Parameter::new("useOpenMMAcceleration", "True").unwrap()),
+        ]
+    }
+
+    fn reparse(cmds: &[Command]) -> Vec<Command> {
+        parse(&to_txt(cmds).unwrap()).into_iter().map(|p| p.command).collect()
+    }
+
+    #[test]
+    fn written_commands_read_back_unchanged() {
+        let cmds = all_commands();
+        assert_eq!(reparse(&cmds), cmds);
+    }
+
+    #[test]
+    fn parsed_commands_read_back_unchanged() {
+        let raw = "\
+            firstStage 1\n\
+            lastStage 1 # comment\n\
+            @ri 0.5\n\
+            reportingInterval @ri\n\
+            temperature warm\n\
+            mobilizer Rigid A 1\n\
+            NtC A 1 2 AA00 0 extra\n\
+            constraint A 1 Weld Ground\n\
+            unknownCommand 3\n\
+            Temperature 300\n\
+            NTC A 1 2 AA00\n\
+            integratorStepSize 0.001\n";
+        let cmds = parse(raw).into_iter().map(|p| p.command).collect::<Vec<_>>();
+
+        assert_eq!(reparse(&cmds), cmds);
+        assert_eq!(cmds[9], Command::Temperature(300.0));
+        assert!(matches!(cmds[10], Command::NtC{ .. }));
+        assert!(matches!(cmds[11], Command::Parameter(_)));
+        for cmd in cmds[2..9].iter() {
+            assert!(matches!(cmd, Command::Other(_)), "{} should not be typed", cmd);
+        }
+    }
+
+    #[test]
+    fn parameters_cannot_shadow_typed_commands() {
+        assert!(Parameter::new("temperature", "300").is_none());
+        assert!(Parameter::new("firstStage", "1").is_none());
+        assert!(Parameter::new("unknownParameter", "1").is_none());
+        assert!(Parameter::new("physicsRadius", "wide").is_none());
+
+        /* A name in both tables would let a parameter stand in for the typed command */
+        for name in vocabulary::names() {
+            if let Some(spec) = vocabulary::lookup_parameter(name) {
+                assert!(std::ptr::eq(spec, vocabulary::lookup(name).unwrap()), "{} is both a command and a parameter", name);
+            }
+        }
+    }
+
+    #[test]
+    fn commands_that_do_not_read_back_are_refused() {
+        let refused = vec![
+            Command::Sequence{ molecule: MoleculeType::Rna, chain: String::from("A#"), first_residue: 1, sequence: String::from("ACGU") },
+            Command::DensityFileName(String::from("my map.mrc")),
+            Command::DensityFileName(String::from("../map.mrc")),
+            Command::Temperature(f64::NAN),
+            Command::NtC{ chain: String::from("A"), first_residue: 1, last_residue: 2, ntc: String::from("AA00"), weight: Some(f64::INFINITY) },
+            Command::Mobilizer{ bond_mobility: String::from("Wobbly"), scope: MobilizerScope::All },
+        ];
+        for cmd in refused {
+            assert!(to_txt(std::slice::from_ref(&cmd)).is_err(), "{} should be refused", cmd);
+        }
+    }
+}
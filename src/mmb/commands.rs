@@ -5,17 +5,18 @@ use std::io::Write;
 use std::path::PathBuf;
 
 use crate::mmb::advanced_params;
+use crate::mmb::ast::{self, Command, MobilizerScope, MoleculeType, StageCondition};
+use crate::mmb::parameters;
+use crate::mmb::validator;
 use crate::server::api;
 
-const KEY_FIRST_STAGE: &'static str = "firstStage";
-const KEY_LAST_STAGE: &'static str = "lastStage";
-const KEY_BASE_ITRS_SF: &'static str = "baseInteractionScaleFactor";
-const KEY_NUM_REP_INTVLS: &'static str = "numReportingIntervals";
+const DNA_RESIDUES: &str = "ACGT";
+const PROTEIN_RESIDUES: &str = "ACDEFGHIKLMNPQRSTVWY";
+const RNA_RESIDUES: &str = "ACGU";
 
 pub struct ParsedRaw {
     pub first_stage: i32,
     pub last_stage: i32,
-    pub num_reporting_intervals: i32,
 }
 
 struct AuthChainMapping {
@@ -25,6 +26,15 @@ struct AuthChainMapping {
 
 type AuthMapping = HashMap<String, AuthChainMapping>;
 
+/* Commands of a single stage of a synthetic job */
+struct Stage<'a> {
+    reporting_interval: f64,
+    num_reporting_intervals: i32,
+    base_interaction_scale_factor: i32,
+    temperature: f64,
+    concrete: &'a api::ConcreteCommands,
+}
+
 impl std::fmt::Display for api::BondMobility {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -39,7 +49,9 @@ impl std::fmt::Display for api::EdgeInteraction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             api::EdgeInteraction::WatsonCrick => write!(f, "WatsonCrick"),
+            api::EdgeInteraction::Hoogsteen => write!(f, "Hoogsteen"),
             api::EdgeInteraction::SugarEdge => write!(f, "SugarEdge"),
+            api::EdgeInteraction::Bifurcated => write!(f, "Bifurcated"),
         }
     }
 }
@@ -80,337 +92,348 @@ fn _mk_auth_mapping(compounds: &Vec<api::Compound>) -> AuthMapping {
     return mapping;
 }
 
-fn base_interactions_to_txt(bis: &Vec<api::BaseInteraction>, mapping: &AuthMapping) -> Result<String, String> {
-    let mut txt = String::new();
-    for bi in bis.iter() {
-        let ch_1 = match mapping.get(&bi.chain_name_1) {
-            Some(v) => v,
-            None => return Err(String::from("No mapping for chain name")),
-        };
-        let ch_2 = match mapping.get(&bi.chain_name_2) {
-            Some(v) => v,
-            None => return Err(String::from("No mapping for chain name")),
-        };
+/* Translates a residue of a synthetic commands chain to the chain and residue number MMB knows */
+fn auth_residue(mapping: &AuthMapping, chain_name: &str, res_no: i32) -> Result<(String, i32), String> {
+    let ch = match mapping.get(chain_name) {
+        Some(v) => v,
+        None => return Err(format!("No mapping for chain {}", chain_name)),
+    };
+    match _get_auth_res_no(ch, res_no) {
+        Some(v) => Ok((ch.auth_name.clone(), v)),
+        None => Err(format!("Residue {} is not in chain {}", res_no, chain_name)),
+    }
+}
 
-        let res_no_auth_1 = match _get_auth_res_no(&ch_1, bi.res_no_1) {
-            Some(v) => v,
-            None => return Err(String::from("Cannot get auth_res_no for res_no_1")),
-        };
-        let res_no_auth_2 = match _get_auth_res_no(&ch_2, bi.res_no_2) {
-            Some(v) => v,
-            None => return Err(String::from("Cannot get auth_res_no for res_no_2")),
-        };
-        txt += format!(
-            "baseInteraction {} {} {} {} {} {} {}\n",
-            ch_1.auth_name, res_no_auth_1, bi.edge_1,
-            ch_2.auth_name, res_no_auth_2, bi.edge_2,
-            bi.orientation
-        ).as_str();
-    }
-    Ok(txt)
+fn base_interactions_to_commands(bis: &[api::BaseInteraction], mapping: &AuthMapping) -> Result<Vec<Command>, String> {
+    let mut cmds = Vec::new();
+    for bi in bis.iter() {
+        let (chain_1, residue_1) = auth_residue(mapping, &bi.chain_name_1, bi.res_no_1)?;
+        let (chain_2, residue_2) = auth_residue(mapping, &bi.chain_name_2, bi.res_no_2)?;
+        cmds.push(Command::BaseInteraction{
+            chain_1, residue_1, edge_1: bi.edge_1.to_string(),
+            chain_2, residue_2, edge_2: bi.edge_2.to_string(),
+            orientation: bi.orientation.to_string(),
+        });
+    }
+    Ok(cmds)
 }
 
-fn compounds_to_txt(compounds: &Vec<api::Compound>) -> Result<String, String> {
-    let mut txt = String::new();
+fn compounds_to_commands(compounds: &[api::Compound]) -> Result<Vec<Command>, String> {
+    let mut cmds = Vec::new();
     for c in compounds.iter() {
-        let ctype = match c.ctype {
-            api::CompoundType::DNA => "DNA",
-            api::CompoundType::Protein => "Protein",
-            api::CompoundType::RNA => "RNA",
+        let molecule = match c.ctype {
+            api::CompoundType::DNA => MoleculeType::Dna,
+            api::CompoundType::Protein => MoleculeType::Protein,
+            api::CompoundType::RNA => MoleculeType::Rna,
         };
-        let res_no = if c.residues.len() > 0 {
-            c.residues[0].auth_number
-        } else {
-            return Err(String::from("Compound does not have any residues"));
+        let first_residue = match c.residues.first() {
+            Some(res) => res.auth_number,
+            None => return Err(String::from("Compound does not have any residues")),
         };
-        txt += format!("{} {} {} {}\n", ctype, c.chain.auth_name, res_no, c.sequence).as_str();
+        cmds.push(Command::Sequence{ molecule, chain: c.chain.auth_name.clone(), first_residue, sequence: c.sequence.clone() });
     }
-    Ok(txt)
+    Ok(cmds)
 }
 
-fn double_helices_to_txt(dhs: &Vec<api::DoubleHelix>, mapping: &AuthMapping) -> Result<String, String> {
-    let mut txt = String::new();
-    for dh in dhs.iter() {
-        let ch_1 = match mapping.get(&dh.chain_name_1) {
-            Some(v) => v,
-            None => return Err(String::from("No mapping for chain name")),
-        };
-        let ch_2 = match mapping.get(&dh.chain_name_2) {
-            Some(v) => v,
-            None => return Err(String::from("No mapping for chain name")),
-        };
+/* Interaction classes are checked only if the parameters file defines any */
+fn check_interaction_classes(ntcs: &api::NtCs, base_interactions: &[api::BaseInteraction]) -> Result<(), String> {
+    let classes = parameters::get();
 
-        let first_res_no_auth_1 = match _get_auth_res_no(&ch_1, dh.first_res_no_1) {
-            Some(v) => v,
-            None => return Err(String::from("Cannot get auth_res_no for first_res_no_1")),
-        };
-        let last_res_no_auth_1 = match _get_auth_res_no(&ch_1, dh.last_res_no_1) {
-            Some(v) => v,
-            None => return Err(String::from("Cannot get auth_res_no last_res_no_1")),
-        };
-        let first_res_no_auth_2 = match _get_auth_res_no(&ch_2, dh.first_res_no_2) {
-            Some(v) => v,
-            None => return Err(String::from("Cannot get auth_res_no for first_res_no_1")),
-        };
-        let last_res_no_auth_2 = match _get_auth_res_no(&ch_2, dh.last_res_no_2) {
-            Some(v) => v,
-            None => return Err(String::from("Cannot get auth_res_no for last_res_no_2")),
-        };
-        txt += format!(
-            "nucleicAcidDuplex {} {} {} {} {} {}\n",
-            ch_1.auth_name, first_res_no_auth_1, last_res_no_auth_1,
-            ch_2.auth_name, first_res_no_auth_2, last_res_no_auth_2
-        ).as_str();
+    if !classes.ntcs.is_empty() {
+        for ntc in ntcs.conformations.iter() {
+            if !classes.ntcs.contains(&ntc.ntc) {
+                return Err(format!("NtC {} on chain {} is not known to MMB", ntc.ntc, ntc.chain_name));
+            }
+        }
     }
-    Ok(txt)
-}
 
-fn filename_to_txt(key: &str, value: &str) -> Result<String, String> {
-    if value.len() < 1 {
-        return Err(String::from("File name of zero length"));
-    }
-    if value.find("/").is_some() {
-        return Err(format!("Invalid character in file name for command {}", key));
+    if !classes.edges.is_empty() {
+        for bi in base_interactions.iter() {
+            for (edge, chain, res_no) in [(&bi.edge_1, &bi.chain_name_1, bi.res_no_1), (&bi.edge_2, &bi.chain_name_2, bi.res_no_2)].iter() {
+                if !classes.edges.contains(&edge.to_string()) {
+                    return Err(format!("Base interaction edge {} of residue {} on chain {} is not known to MMB", edge, res_no, chain));
+                }
+            }
+            if !classes.orientations.contains(&bi.orientation.to_string()) {
+                return Err(format!(
+                    "Base interaction orientation {} between residues {} on chain {} and {} on chain {} is not known to MMB",
+                    bi.orientation, bi.res_no_1, bi.chain_name_1, bi.res_no_2, bi.chain_name_2
+                ));
+            }
+        }
     }
-    if value.find("\\").is_some() {
-        return Err(format!("Invalid character in file name for command {}", key));
+
+    Ok(())
+}
+
+/* Weight scales the force of a single NtC on top of NtCForceScaleFactor */
+fn check_ntc_weights(ntcs: &api::NtCs) -> Result<(), String> {
+    for ntc in ntcs.conformations.iter() {
+        if !ntc.weight.is_finite() || ntc.weight <= 0.0 {
+            return Err(format!(
+                "NtC {} on chain {} between residues {} and {} has weight {}, weights must be positive",
+                ntc.ntc, ntc.chain_name, ntc.first_res_no, ntc.last_res_no, ntc.weight
+            ));
+        }
     }
 
-    Ok(keyed_to_txt(key, value))
+    Ok(())
 }
 
-fn get_value_from_raw<T, F>(lines: &Vec<&str>, key: &'static str, converter: F) -> Option<T> where F: Fn(&str) -> Option<T> {
-    let lwr_key = key.to_lowercase();
+fn check_compound(c: &api::Compound) -> Result<(), String> {
+    let (kind, alphabet) = match c.ctype {
+        api::CompoundType::DNA => ("DNA", DNA_RESIDUES),
+        api::CompoundType::Protein => ("protein", PROTEIN_RESIDUES),
+        api::CompoundType::RNA => ("RNA", RNA_RESIDUES),
+    };
 
-    for l in lines {
-        let segments = l.trim().split(" ").collect::<Vec<_>>();
-        if segments.len() < 2 {
-            continue;
-        }
+    if c.sequence.is_empty() {
+        return Err(format!("Compound on chain {} has an empty sequence", c.chain.name));
+    }
+    if let Some((pos, code)) = c.sequence.chars().enumerate().find(|(_, code)| !alphabet.contains(*code)) {
+        return Err(format!("Compound on chain {}: residue {} of the sequence ({}) is not a valid {} residue code", c.chain.name, pos + 1, code, kind));
+    }
 
-        if segments[0].to_lowercase() != lwr_key {
-            continue;
+    let seq_len = c.sequence.chars().count();
+    if c.residues.len() != seq_len {
+        return Err(format!("Compound on chain {} has {} residues but its sequence is {} residues long", c.chain.name, c.residues.len(), seq_len));
+    }
+    for (pos, pair) in c.residues.windows(2).enumerate() {
+        if pair[1].auth_number <= pair[0].auth_number {
+            return Err(format!(
+                "Compound on chain {}: residue {} has auth number {} which does not follow auth number {} of the previous residue",
+                c.chain.name, pos + 2, pair[1].auth_number, pair[0].auth_number
+            ));
         }
+    }
+
+    Ok(())
+}
+
+fn check_compounds(compounds: &[api::Compound]) -> Result<(), String> {
+    for (idx, c) in compounds.iter().enumerate() {
+        check_compound(c)?;
 
-        for idx in 1..segments.len() {
-            if segments[idx].len() < 1 {
-                continue;
+        for other in compounds[..idx].iter() {
+            if other.chain.name == c.chain.name {
+                return Err(format!("Chain {} is used by more than one compound", c.chain.name));
+            }
+            if other.chain.auth_name == c.chain.auth_name {
+                return Err(format!("Auth chain name {} is used by more than one compound", c.chain.auth_name));
             }
-            return converter(segments[idx]);
         }
     }
 
-    None
+    Ok(())
 }
 
-fn keyed_to_txt<T: std::fmt::Display>(key: &str, value: T) -> String {
-    format!("{} {}\n", key, value)
+fn double_helices_to_commands(dhs: &[api::DoubleHelix], mapping: &AuthMapping) -> Result<Vec<Command>, String> {
+    let mut cmds = Vec::new();
+    for dh in dhs.iter() {
+        let (chain_1, first_residue_1) = auth_residue(mapping, &dh.chain_name_1, dh.first_res_no_1)?;
+        let (_, last_residue_1) = auth_residue(mapping, &dh.chain_name_1, dh.last_res_no_1)?;
+        let (chain_2, first_residue_2) = auth_residue(mapping, &dh.chain_name_2, dh.first_res_no_2)?;
+        let (_, last_residue_2) = auth_residue(mapping, &dh.chain_name_2, dh.last_res_no_2)?;
+        cmds.push(Command::NucleicAcidDuplex{ chain_1, first_residue_1, last_residue_1, chain_2, first_residue_2, last_residue_2 });
+    }
+    Ok(cmds)
 }
 
-fn mobilizers_to_txt(mobilizers: &Vec<api::Mobilizer>, mapping: &AuthMapping) -> Result<String, String> {
-    let mut txt = String::new();
-
-    for m in mobilizers.iter() {
-        let mut line = format!("mobilizer {}", m.bond_mobility);
-
-        if m.chain.is_some() {
-            let ch = match mapping.get(m.chain.as_ref().unwrap()) {
-                Some(v) => v,
-                None => return Err(String::from("No mapping for chain")),
-            };
+fn check_file_name(key: &str, value: &str) -> Result<(), String> {
+    if value.len() < 1 {
+        return Err(String::from("File name of zero length"));
+    }
+    if value.find("/").is_some() {
+        return Err(format!("Invalid character in file name for command {}", key));
+    }
+    if value.find("\\").is_some() {
+        return Err(format!("Invalid character in file name for command {}", key));
+    }
 
-            line.push_str(format!(" {}", ch.auth_name).as_str());
+    Ok(())
+}
 
-            if m.first_residue.is_some() && m.last_residue.is_some() {
-                let auth_first = match _get_auth_res_no(&ch, m.first_residue.unwrap()) {
-                    Some(v) => v,
-                    None => return Err(String::from("No auth_res_no for first_residue")),
-                };
-                let auth_last = match _get_auth_res_no(&ch, m.last_residue.unwrap()) {
-                    Some(v) => v,
-                    None => return Err(String::from("No auth_res_no for last_residue")),
+fn mobilizers_to_commands(mobilizers: &[api::Mobilizer], mapping: &AuthMapping) -> Result<Vec<Command>, String> {
+    let mut cmds = Vec::new();
+    for m in mobilizers.iter() {
+        let scope = match &m.chain {
+            Some(chain_name) => {
+                let auth_name = match mapping.get(chain_name) {
+                    Some(v) => v.auth_name.clone(),
+                    None => return Err(format!("No mapping for chain {}", chain_name)),
                 };
-                line.push_str(format!(" {} {}", auth_first, auth_last).as_str());
-            }
-        }
-
-        txt += (line + "\n").as_str();
+                match (m.first_residue, m.last_residue) {
+                    (Some(first), Some(last)) => MobilizerScope::Residues{
+                        chain: auth_name,
+                        first: auth_residue(mapping, chain_name, first)?.1,
+                        last: auth_residue(mapping, chain_name, last)?.1,
+                    },
+                    _ => MobilizerScope::Chain(auth_name),
+                }
+            },
+            None => MobilizerScope::All,
+        };
+        cmds.push(Command::Mobilizer{ bond_mobility: m.bond_mobility.to_string(), scope });
     }
-
-    Ok(txt)
+    Ok(cmds)
 }
 
-fn ntcs_to_txt(ntcs: &api::NtCs, mapping: &AuthMapping) -> Result<String, String> {
-    let mut txt = String::new();
+fn ntcs_to_commands(ntcs: &api::NtCs, mapping: &AuthMapping) -> Result<Vec<Command>, String> {
+    let mut cmds = Vec::new();
     if ntcs.conformations.len() == 0 {
-        return Ok(txt);
+        return Ok(cmds);
     }
 
     for ntc in ntcs.conformations.iter() {
-        let ch = match mapping.get(&ntc.chain_name) {
-            Some(v) => v,
-            None => return Err(String::from("No mapping for chain name")),
-        };
-        let first_res_no_auth = match _get_auth_res_no(&ch, ntc.first_res_no) {
-            Some(v) => v,
-            None => return Err(String::from("Cannot get auth_res_no for first_res_no")),
-        };
-        let last_res_no_auth = match _get_auth_res_no(&ch, ntc.last_res_no) {
-            Some(v) => v,
-            None => return Err(String::from("Cannot get auth_res_no for last_res_no")),
-        };
-        txt += format!(
-            "NtC {} {} {} {}\n",
-            ch.auth_name, first_res_no_auth, last_res_no_auth, ntc.ntc
-        ).as_str();
+        let (chain, first_residue) = auth_residue(mapping, &ntc.chain_name, ntc.first_res_no)?;
+        let (_, last_residue) = auth_residue(mapping, &ntc.chain_name, ntc.last_res_no)?;
+        cmds.push(Command::NtC{ chain, first_residue, last_residue, ntc: ntc.ntc.clone(), weight: Some(ntc.weight) });
     }
-    txt += format!("NtCForceScaleFactor {}\n", ntcs.force_scale_factor).as_str();
-    Ok(txt)
-}
-
-fn common_commands_to_txt(common: &api::Commands) -> Result<String, String> {
-    let mut txt = String::new();
+    cmds.push(Command::NtCForceScaleFactor(ntcs.force_scale_factor));
 
-    txt += keyed_to_txt(KEY_FIRST_STAGE, common.stage).as_str();
-    txt += keyed_to_txt(KEY_LAST_STAGE, common.stage).as_str();
+    Ok(cmds)
+}
 
-    txt += keyed_to_txt("reportingInterval", common.reporting_interval).as_str();
-    txt += keyed_to_txt(KEY_NUM_REP_INTVLS, common.num_reporting_intervals).as_str();
+fn common_commands(stage: &Stage) -> Vec<Command> {
+    vec![
+        Command::ReportingInterval(stage.reporting_interval),
+        Command::NumReportingIntervals(stage.num_reporting_intervals),
+        Command::BaseInteractionScaleFactor(stage.base_interaction_scale_factor as f64),
+        Command::Temperature(stage.temperature),
+    ]
+}
 
-    txt += keyed_to_txt(KEY_BASE_ITRS_SF, common.base_interaction_scale_factor).as_str();
-    txt += keyed_to_txt("temperature", common.temperature).as_str();
+fn density_fit_commands(common: &Stage, concrete: &api::DensityFitCommands) -> Result<Vec<Command>, String> {
+    let mut cmds = common_commands(common);
+    let auth_mapping = _mk_auth_mapping(&concrete.compounds);
 
-    Ok(txt)
+    if concrete.set_default_MD_parameters {
+        cmds.push(Command::SetDefaultMDParameters);
+    }
+    check_file_name("loadSequencesFromPdb", &concrete.structure_file_name)?;
+    cmds.push(Command::LoadSequencesFromPdb(Some(concrete.structure_file_name.clone())));
+    check_file_name("density densityFileName", &concrete.density_map_file_name)?;
+    cmds.push(Command::DensityFileName(concrete.density_map_file_name.clone()));
+    cmds.append(&mut mobilizers_to_commands(&concrete.mobilizers, &auth_mapping)?);
+    cmds.append(&mut ntcs_to_commands(&concrete.ntcs, &auth_mapping)?);
+    cmds.push(Command::FitToDensity);
+
+    Ok(cmds)
 }
 
-fn density_fit_commands_to_txt(common: &api::Commands, concrete: &api::DensityFitCommands) -> Result<String, String> {
-    match common_commands_to_txt(common) {
-        Ok(mut txt) => {
-            let auth_mapping = _mk_auth_mapping(&concrete.compounds);
+fn standard_commands(common: &Stage, concrete: &api::StandardCommands) -> Result<Vec<Command>, String> {
+    let mut cmds = common_commands(common);
+    let auth_mapping = _mk_auth_mapping(&concrete.compounds);
 
-            if concrete.set_default_MD_parameters {
-                txt += "setDefaultMDParameters\n";
-            }
+    if concrete.set_default_MD_parameters {
+        cmds.push(Command::SetDefaultMDParameters);
+    }
+    cmds.append(&mut compounds_to_commands(&concrete.compounds)?);
+    cmds.append(&mut double_helices_to_commands(&concrete.double_helices, &auth_mapping)?);
+    cmds.append(&mut base_interactions_to_commands(&concrete.base_interactions, &auth_mapping)?);
+    cmds.append(&mut ntcs_to_commands(&concrete.ntcs, &auth_mapping)?);
+    cmds.append(&mut mobilizers_to_commands(&concrete.mobilizers, &auth_mapping)?);
+    cmds.append(&mut advanced_params::to_commands(&concrete.adv_params)?);
+
+    Ok(cmds)
+}
 
-            match filename_to_txt("loadSequencesFromPdb", &concrete.structure_file_name) {
-                Ok(s) => txt += s.as_str(),
-                Err(e) => return Err(e),
-            };
-            match filename_to_txt("density densityFileName", &concrete.density_map_file_name) {
-                Ok(s) => txt += s.as_str(),
-                Err(e) => return Err(e),
-            };
-            txt += match mobilizers_to_txt(&concrete.mobilizers, &auth_mapping) {
-                Ok(v) => v,
-                Err(e) => return Err(e),
-            }.as_str();
-            txt += match ntcs_to_txt(&concrete.ntcs, &auth_mapping) {
-                Ok(v) => v,
-                Err(e) => return Err(e),
-            }.as_str();
-            txt += "fitToDensity\n";
-
-            Ok(txt)
+fn stage_commands(stage: &Stage) -> Result<Vec<Command>, String> {
+    match stage.concrete {
+        api::ConcreteCommands::DensityFit(v) => match density_fit_commands(stage, v) {
+            Ok(cmds) => Ok(cmds),
+            Err(e) => Err(format!("Invalid MMB commands for density fit job: {}", e)),
+        },
+        api::ConcreteCommands::Standard(v) => match standard_commands(stage, v) {
+            Ok(cmds) => Ok(cmds),
+            Err(e) => Err(format!("Invalid MMB commands for standard job: {}", e)),
         },
-        Err(e) => Err(e),
     }
 }
 
-fn standard_commands_to_txt(common: &api::Commands, concrete: &api::StandardCommands) -> Result<String, String> {
-    match common_commands_to_txt(common) {
-        Ok(mut txt) => {
-            if concrete.set_default_MD_parameters {
-                txt += "setDefaultMDParameters\n";
-            }
-
-            let auth_mapping = _mk_auth_mapping(&concrete.compounds);
-
-            match compounds_to_txt(&concrete.compounds) {
-                Ok(s) => txt += s.as_str(),
-                Err(e) => return Err(e),
-            };
-            txt += match double_helices_to_txt(&concrete.double_helices, &auth_mapping) {
-                Ok(v) => v,
-                Err(e) => return Err(e),
-            }.as_str();
-            txt += match base_interactions_to_txt(&concrete.base_interactions, &auth_mapping) {
-                Ok(v) => v,
-                Err(e) => return Err(e),
-            }.as_str();
-            txt += match ntcs_to_txt(&concrete.ntcs, &auth_mapping) {
-                Ok(v) => v,
-                Err(e) => return Err(e),
-            }.as_str();
-
-            txt += match mobilizers_to_txt(&concrete.mobilizers, &auth_mapping) {
-                Ok(v) => v,
-                Err(e) => return Err(e),
-            }.as_str();
-            match advanced_params::to_txt(&concrete.adv_params) {
-                Ok(s) => txt += s.as_str(),
-                Err(e) => return Err(e.to_string()),
-            }
+fn stages_of(commands: &api::Commands) -> Vec<Stage<'_>> {
 
-            Ok(txt)
-        },
-        Err(e) => Err(e)
+    let mut stages = vec![
+        Stage{
+            reporting_interval: commands.reporting_interval,
+            num_reporting_intervals: commands.num_reporting_intervals,
+            base_interaction_scale_factor: commands.base_interaction_scale_factor,
+            temperature: commands.temperature,
+            concrete: &commands.concrete,
+        }
+    ];
+    for next in commands.next_stages.iter() {
+        stages.push(Stage{
+            reporting_interval: next.reporting_interval,
+            num_reporting_intervals: next.num_reporting_intervals,
+            base_interaction_scale_factor: next.base_interaction_scale_factor,
+            temperature: next.temperature,
+            concrete: &next.concrete,
+        });
     }
+
+    stages
 }
 
+/* Stage numbers covered by synthetic commands */
+pub fn stage_range(commands: &api::Commands) -> (i32, i32) {
+    (commands.stage, commands.stage + commands.next_stages.len() as i32)
+}
+
+/* Stage range of raw commands. Commands that the validator finds errors in are refused. */
 pub fn parse_raw(raw: &str) -> Result<ParsedRaw, String> {
-    let converter = |s: &str| -> Option<i32> {
-        match s.parse::<i32>() {
-            Ok(v) => Some(v),
-            Err(_) => None,
-        }
-    };
-    let lines = raw.split("\n").collect::<Vec<_>>();
+    let diagnostics = validator::validate(raw);
+    if let Some(error) = diagnostics.iter().find(|d| d.severity == validator::Severity::Error) {
+        return Err(error.to_string());
+    }
 
-    let first_stage = match get_value_from_raw(&lines, KEY_FIRST_STAGE, converter) {
-        Some(v) => v,
-        None => return Err(format!("{} was not specified or is invalid", KEY_FIRST_STAGE)),
-    };
-    let last_stage = match get_value_from_raw(&lines, KEY_LAST_STAGE, converter) {
-        Some(v) => v,
-        None => return Err(format!("{} was not specified or is invalid", KEY_LAST_STAGE)),
-    };
-    let num_reporting_intervals = match get_value_from_raw(&lines, KEY_NUM_REP_INTVLS, converter) {
-        Some(v) => v,
-        None => return Err(format!("{} was not specified or is invalid", KEY_NUM_REP_INTVLS)),
-    };
+    /* The validator has checked that the values are present and valid, the first one of each applies */
+    let parsed = ast::parse(raw);
+    let first_stage = parsed.iter().find_map(|p| if let Command::FirstStage(v) = p.command { Some(v) } else { None });
+    let last_stage = parsed.iter().find_map(|p| if let Command::LastStage(v) = p.command { Some(v) } else { None });
 
-    if first_stage < 1 {
-        return Err(format!("{} must be positive", KEY_FIRST_STAGE));
-    }
-    if first_stage != last_stage {
-        return Err(String::from("Multi-stage jobs are currently not supported"));
-    }
-    if num_reporting_intervals < 1 {
-        return Err(format!("{} must be positive", KEY_NUM_REP_INTVLS));
+    match (first_stage, last_stage) {
+        (Some(first_stage), Some(last_stage)) => Ok(ParsedRaw{ first_stage, last_stage }),
+        _ => Err(String::from("Stage range was not specified")),
     }
+}
 
-    Ok(
-        ParsedRaw {
-            first_stage,
-            last_stage,
-            num_reporting_intervals
+/* Catches mistakes in synthetic commands that MMB would otherwise report only once the job runs */
+pub fn validate(commands: &api::Commands) -> Result<(), String> {
+    for (idx, stage) in stages_of(commands).iter().enumerate() {
+        let (compounds, ntcs, base_interactions) = match stage.concrete {
+            api::ConcreteCommands::DensityFit(v) => (&v.compounds, &v.ntcs, &[][..]),
+            api::ConcreteCommands::Standard(v) => (&v.compounds, &v.ntcs, &v.base_interactions[..]),
+        };
+        let checked = check_compounds(compounds)
+            .and_then(|_| check_interaction_classes(ntcs, base_interactions))
+            .and_then(|_| check_ntc_weights(ntcs));
+        if let Err(e) = checked {
+            return Err(format!("Stage {}: {}", commands.stage + idx as i32, e));
         }
-    )
+    }
+
+    Ok(())
 }
 
 pub fn write(path: &PathBuf, commands: &api::Commands) -> Result<(), String> {
-    let raw_commands = match &commands.concrete {
-        api::ConcreteCommands::DensityFit(v) => match density_fit_commands_to_txt(&commands, &v) {
-            Ok(raw_commands) => raw_commands,
-            Err(e) => return Err(format!("Invalid MMB commands for density fit job: {}", e.to_string())),
-        },
-        api::ConcreteCommands::Standard(v) => match standard_commands_to_txt(&commands, &v) {
-            Ok(raw_commands) => raw_commands,
-            Err(e) => return Err(format!("Invalid MMB commands for standard job: {}", e.to_string())),
-        },
-    };
+    let (first_stage, last_stage) = stage_range(commands);
+
+    let mut cmds = vec![Command::FirstStage(first_stage), Command::LastStage(last_stage)];
+
+    let stages = stages_of(commands);
+    if stages.len() == 1 {
+        cmds.append(&mut stage_commands(&stages[0])?);
+    } else {
+        /* Each stage gets a complete set of commands that MMB reads only when it runs that stage */
+        for (idx, stage) in stages.iter().enumerate() {
+            cmds.push(Command::ReadBlockStart{ condition: StageCondition::At, stage: first_stage + idx as i32 });
+            cmds.append(&mut stage_commands(stage)?);
+            cmds.push(Command::ReadBlockEnd);
+        }
+    }
 
-    write_raw(path, &raw_commands)
+    write_raw(path, &ast::to_txt(&cmds)?)
 }
 
 pub fn write_raw(path: &PathBuf, raw_commands: &str) -> Result<(), String> {
@@ -1,7 +1,12 @@
+pub mod advanced_params;
+pub mod ast;
 pub mod commands;
 pub mod examples;
 pub mod additional_files;
-mod advanced_params;
+pub mod parameters;
+pub mod raw_import;
+pub mod validator;
+mod vocabulary;
 
 use serde_derive::Deserialize;
 
@@ -18,10 +23,21 @@ pub enum State {
     NotStarted,
     Queued,
     Running,
+    Stopping,
+    Held,
+    Suspended,
+    Cancelled,
     Failed,
     Finished,
 }
 
+impl State {
+    /* Tells whether the job is still known to the executor, that is waiting, running or paused */
+    pub fn is_active(&self) -> bool {
+        matches!(self, State::Queued | State::Running | State::Stopping | State::Held | State::Suspended)
+    }
+}
+
 impl std::fmt::Display for State {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         std::fmt::Debug::fmt(self, f)
@@ -33,6 +49,9 @@ pub struct Progress {
     pub state: State,
     pub step: i32,
     pub total_steps: i32,
+    /* Not reported by older versions of MMB */
+    #[serde(default)]
+    pub stage: Option<i32>,
 }
 
 pub fn trajectory_file_name(stage: i32) -> String {
@@ -0,0 +1,112 @@
+use lazy_static::lazy_static;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+const MOLECULE_TYPES: &[&str] = &["DNA", "Protein", "RNA"];
+const NTC_MARKER: &str = "NtC";
+
+/* Interaction classes that the MMB parameters file defines parameters for */
+pub struct InteractionClasses {
+    pub ntcs: BTreeSet<String>,
+    pub edges: BTreeSet<String>,
+    pub orientations: BTreeSet<String>,
+}
+
+lazy_static! {
+    static ref CLASSES: RwLock<Arc<InteractionClasses>> = RwLock::new(
+        Arc::new(
+            InteractionClasses{
+                ntcs: BTreeSet::new(),
+                edges: BTreeSet::new(),
+                orientations: BTreeSet::new(),
+            }
+        )
+    );
+}
+
+/* Rows of the parameters file that describe base pairs start with the molecule type
+ * and the names of the two residues, followed by the two Leontis-Westhof edges and
+ * the glycosidic bond orientation. Rows that describe NtCs have the name of the
+ * class right after the NtC marker. */
+fn parse(content: &str) -> InteractionClasses {
+    let mut classes = InteractionClasses{ ntcs: BTreeSet::new(), edges: BTreeSet::new(), orientations: BTreeSet::new() };
+
+    for line in content.lines() {
+        let cells = line.split(',').map(|c| c.trim()).collect::<Vec<_>>();
+
+        if let Some(idx) = cells.iter().position(|c| *c == NTC_MARKER) {
+            if let Some(ntc) = cells.get(idx + 1).filter(|c| !c.is_empty()) {
+                classes.ntcs.insert(String::from(*ntc));
+            }
+            continue;
+        }
+
+        if cells.len() < 6 || !MOLECULE_TYPES.contains(&cells[0]) {
+            continue;
+        }
+        for edge in cells[3..5].iter().filter(|c| !c.is_empty()) {
+            classes.edges.insert(String::from(*edge));
+        }
+        if !cells[5].is_empty() {
+            classes.orientations.insert(String::from(cells[5]));
+        }
+    }
+
+    classes
+}
+
+pub fn get() -> Arc<InteractionClasses> {
+    CLASSES.read().unwrap().clone()
+}
+
+pub fn load(path: &Path) -> Result<(), String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    *CLASSES.write().unwrap() = Arc::new(parse(&content));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Rows in the layout described above, mixed with rows that do not describe interaction classes */
+    const EXCERPT: &str = "\
+RNA,A,U,WatsonCrick,WatsonCrick,Cis,HarmonicInverse,0.0,0.0,0.0
+RNA,G,C,WatsonCrick,WatsonCrick,Cis,HarmonicInverse,0.0,0.0,0.0
+RNA,A,G,Hoogsteen,SugarEdge,Trans,HarmonicInverse,0.0,0.0,0.0
+DNA,A,T,WatsonCrick,WatsonCrick,Cis,HarmonicInverse,0.0,0.0,0.0
+Protein,ALA,ALA,Bifurcated,,Cis,HarmonicInverse,0.0,0.0,0.0
+NtC,AA00,C4',C3',O3',P,0.0,1.0
+NtC,BB01,C4',C3',O3',P,0.0,1.0
+NtC,,C4',C3',O3',P,0.0,1.0
+Ion,MG,MG,WatsonCrick,Hoogsteen,Cis
+RNA,A,U
+";
+
+    fn set(values: &[&str]) -> BTreeSet<String> {
+        values.iter().map(|v| String::from(*v)).collect()
+    }
+
+    #[test]
+    fn base_pairs_and_ntcs_are_read() {
+        let classes = parse(EXCERPT);
+
+        assert_eq!(classes.edges, set(&["Bifurcated", "Hoogsteen", "SugarEdge", "WatsonCrick"]));
+        assert_eq!(classes.orientations, set(&["Cis", "Trans"]));
+        assert_eq!(classes.ntcs, set(&["AA00", "BB01"]));
+    }
+
+    #[test]
+    fn unrelated_content_yields_no_classes() {
+        let classes = parse("# comment\n\nsomething,else,entirely\n");
+
+        assert!(classes.edges.is_empty());
+        assert!(classes.orientations.is_empty());
+        assert!(classes.ntcs.is_empty());
+    }
+}
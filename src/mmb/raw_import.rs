@@ -0,0 +1,540 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::mmb::advanced_params;
+use crate::mmb::ast::{self, Command, MobilizerScope, MoleculeType, ParsedCommand, StageCondition};
+use crate::mmb::commands;
+use crate::mmb::vocabulary;
+use crate::server::api;
+
+/* Line of raw commands that has no counterpart in synthetic commands */
+pub struct SkippedLine {
+    pub line: usize,
+    pub text: String,
+    pub reason: String,
+}
+
+pub struct Imported {
+    pub commands: api::Commands,
+    pub skipped: Vec<SkippedLine>,
+}
+
+/* First residue and length of a chain as given by its sequence command */
+struct ChainResidues {
+    first: i32,
+    len: i32,
+}
+
+struct ImportedStage {
+    reporting_interval: f64,
+    num_reporting_intervals: i32,
+    base_interaction_scale_factor: i32,
+    temperature: f64,
+    concrete: api::ConcreteCommands,
+}
+
+type Chains = HashMap<String, ChainResidues>;
+
+fn skip(skipped: &mut Vec<SkippedLine>, p: &ParsedCommand, reason: &str) {
+    skipped.push(SkippedLine{ line: p.line, text: String::from(p.text.trim()), reason: String::from(reason) });
+}
+
+fn bond_mobility(text: &str) -> Option<api::BondMobility> {
+    match text.to_lowercase().as_str() {
+        "rigid" => Some(api::BondMobility::Rigid),
+        "torsion" => Some(api::BondMobility::Torsion),
+        "free" => Some(api::BondMobility::Free),
+        _ => None,
+    }
+}
+
+fn edge(text: &str) -> Option<api::EdgeInteraction> {
+    match text.to_lowercase().as_str() {
+        "watsoncrick" => Some(api::EdgeInteraction::WatsonCrick),
+        "hoogsteen" => Some(api::EdgeInteraction::Hoogsteen),
+        "sugaredge" => Some(api::EdgeInteraction::SugarEdge),
+        "bifurcated" => Some(api::EdgeInteraction::Bifurcated),
+        _ => None,
+    }
+}
+
+fn orientation(text: &str) -> Option<api::Orientation> {
+    match text.to_lowercase().as_str() {
+        "cis" => Some(api::Orientation::Cis),
+        "trans" => Some(api::Orientation::Trans),
+        _ => None,
+    }
+}
+
+fn compound(molecule: MoleculeType, chain: &str, first_residue: i32, sequence: &str) -> Result<api::Compound, &'static str> {
+    if !sequence.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err("Only sequences of one-letter residue codes can be represented");
+    }
+
+    let len = i32::try_from(sequence.len()).map_err(|_| "Sequence is too long")?;
+    let residues = (0..len)
+        .map(|idx| first_residue.checked_add(idx).map(|auth_number| api::ResidueNumber{ number: idx + 1, auth_number }))
+        .collect::<Option<Vec<_>>>()
+        .ok_or("Residue numbers of the sequence are out of range")?;
+
+    Ok(
+        api::Compound{
+            chain: api::Chain{ name: String::from(chain), auth_name: String::from(chain) },
+            ctype: match molecule {
+                MoleculeType::Dna => api::CompoundType::DNA,
+                MoleculeType::Protein => api::CompoundType::Protein,
+                MoleculeType::Rna => api::CompoundType::RNA,
+            },
+            sequence: String::from(sequence),
+            residues,
+        }
+    )
+}
+
+/* Synthetic commands refer to residues by their position in the chain */
+fn residue(chains: &Chains, chain: &str, auth: i32) -> Result<i32, &'static str> {
+    let ch = match chains.get(chain) {
+        Some(v) => v,
+        None => return Err("Chain has no sequence command"),
+    };
+
+    let no = match auth.checked_sub(ch.first).and_then(|v| v.checked_add(1)) {
+        Some(v) => v,
+        None => return Err("Residue is outside of the chain"),
+    };
+    if no < 1 || no > ch.len {
+        return Err("Residue is outside of the chain");
+    }
+    Ok(no)
+}
+
+/* Residues are given as (chain, first residue, last residue) */
+fn double_helix(chains: &Chains, first: (&str, i32, i32), second: (&str, i32, i32)) -> Result<api::DoubleHelix, &'static str> {
+    Ok(
+        api::DoubleHelix{
+            chain_name_1: String::from(first.0),
+            first_res_no_1: residue(chains, first.0, first.1)?,
+            last_res_no_1: residue(chains, first.0, first.2)?,
+            chain_name_2: String::from(second.0),
+            first_res_no_2: residue(chains, second.0, second.1)?,
+            last_res_no_2: residue(chains, second.0, second.2)?,
+        }
+    )
+}
+
+/* Residues are given as (chain, residue, edge) */
+fn base_interaction(chains: &Chains, first: (&str, i32, &str), second: (&str, i32, &str), orient: &str) -> Result<api::BaseInteraction, &'static str> {
+    let unsupported_edge = "Only WatsonCrick, Hoogsteen, SugarEdge and Bifurcated edges can be represented";
+
+    Ok(
+        api::BaseInteraction{
+            chain_name_1: String::from(first.0),
+            res_no_1: residue(chains, first.0, first.1)?,
+            edge_1: edge(first.2).ok_or(unsupported_edge)?,
+            chain_name_2: String::from(second.0),
+            res_no_2: residue(chains, second.0, second.1)?,
+            edge_2: edge(second.2).ok_or(unsupported_edge)?,
+            orientation: orientation(orient).ok_or("Only Cis and Trans orientations can be represented")?,
+        }
+    )
+}
+
+fn ntc(chains: &Chains, chain: &str, first_residue: i32, last_residue: i32, ntc: &str, weight: Option<f64>) -> Result<api::NtCConformation, &'static str> {
+    Ok(
+        api::NtCConformation{
+            chain_name: String::from(chain),
+            first_res_no: residue(chains, chain, first_residue)?,
+            last_res_no: residue(chains, chain, last_residue)?,
+            ntc: String::from(ntc),
+            weight: weight.unwrap_or(api::DEFAULT_NTC_WEIGHT),
+        }
+    )
+}
+
+fn mobilizer(chains: &Chains, mobility: &str, scope: &MobilizerScope) -> Result<api::Mobilizer, &'static str> {
+    let bond_mobility = bond_mobility(mobility).ok_or("Only Rigid, Torsion and Free mobilizers can be represented")?;
+    let (chain, residues) = match scope {
+        MobilizerScope::All => return Ok(api::Mobilizer{ bond_mobility, chain: None, first_residue: None, last_residue: None }),
+        MobilizerScope::Chain(chain) => (chain, None),
+        MobilizerScope::Residues{ chain, first, last } => (chain, Some((*first, *last))),
+    };
+    if !chains.contains_key(chain) {
+        return Err("Chain has no sequence command");
+    }
+    let (first_residue, last_residue) = match residues {
+        Some((first, last)) => (Some(residue(chains, chain, first)?), Some(residue(chains, chain, last)?)),
+        None => (None, None),
+    };
+
+    Ok(api::Mobilizer{ bond_mobility, chain: Some(chain.clone()), first_residue, last_residue })
+}
+
+fn import_stage(parsed: &Vec<&ParsedCommand>, stage: i32, skipped: &mut Vec<SkippedLine>) -> Result<ImportedStage, String> {
+    let is_density_fit = parsed.iter().any(|p| match p.command {
+        Command::LoadSequencesFromPdb(_) | Command::DensityFileName(_) | Command::FitToDensity => true,
+        Command::Other(ref cmd) => cmd.name().eq_ignore_ascii_case("density"),
+        _ => false,
+    });
+
+    let mut reporting_interval = None;
+    let mut num_reporting_intervals = None;
+    let mut base_interaction_scale_factor = None;
+    let mut temperature = None;
+    let mut set_default_md_parameters = false;
+    let mut structure_file_name = None;
+    let mut density_map_file_name = None;
+    let mut fit_to_density = false;
+    let mut compounds = Vec::new();
+    let mut adv_params = api::JsonAdvancedParameters::new();
+    let mut ntc_force_scale_factor = None;
+    /* Commands that refer to residues are resolved once all chains are known */
+    let mut deferred = Vec::new();
+    /* Synthetic commands set the advanced parameters after setDefaultMDParameters. Parameter that MMB
+     * reads before setDefaultMDParameters would not end up with the same value. */
+    let set_defaults_line = parsed.iter().filter(|p| matches!(p.command, Command::SetDefaultMDParameters)).map(|p| p.line).max();
+
+    for p in parsed.iter() {
+        match &p.command {
+            Command::FirstStage(_) | Command::LastStage(_) => skip(skipped, p, "Stage range cannot be changed within a stage"),
+            Command::ReadBlockStart{ .. } | Command::ReadBlockEnd => (),
+            Command::ReportingInterval(v) => reporting_interval = Some(*v),
+            Command::NumReportingIntervals(v) => num_reporting_intervals = Some(*v),
+            Command::BaseInteractionScaleFactor(v) => match v.fract() == 0.0 {
+                true => base_interaction_scale_factor = Some(*v as i32),
+                false => skip(skipped, p, "Only integral scale factors can be represented"),
+            },
+            Command::Temperature(v) => temperature = Some(*v),
+            Command::SetDefaultMDParameters => set_default_md_parameters = true,
+            Command::Sequence{ molecule, chain, first_residue, sequence } => {
+                if is_density_fit {
+                    skip(skipped, p, "Density fit jobs take sequences from the structure file");
+                    continue;
+                }
+                match compound(*molecule, chain, *first_residue, sequence) {
+                    Ok(c) => {
+                        if compounds.iter().any(|other: &api::Compound| other.chain.auth_name == c.chain.auth_name) {
+                            skip(skipped, p, "Chain is defined more than once");
+                        } else {
+                            compounds.push(c);
+                        }
+                    },
+                    Err(reason) => skip(skipped, p, reason),
+                };
+            },
+            Command::LoadSequencesFromPdb(file) => match file {
+                Some(name) => structure_file_name = Some(name.clone()),
+                None => skip(skipped, p, "Structure file name must be given"),
+            },
+            Command::DensityFileName(name) => density_map_file_name = Some(name.clone()),
+            Command::FitToDensity => fit_to_density = true,
+            Command::NtCForceScaleFactor(v) => ntc_force_scale_factor = Some(*v),
+            Command::NucleicAcidDuplex{ .. } | Command::BaseInteraction{ .. } | Command::NtC{ .. } | Command::Mobilizer{ .. } => deferred.push(p),
+            Command::Parameter(param) => {
+                /* MMB does not care about the case of parameter names, the schema spells them in one way */
+                let name = vocabulary::lookup_parameter(param.name()).map_or(param.name(), |spec| spec.name);
+                if is_density_fit {
+                    skip(skipped, p, "Command has no counterpart in synthetic commands");
+                } else if set_defaults_line.is_some_and(|line| p.line < line) {
+                    skip(skipped, p, "Parameter set before setDefaultMDParameters cannot be represented");
+                } else if adv_params.contains_key(name) {
+                    skip(skipped, p, "Parameter is set more than once");
+                } else {
+                    match advanced_params::value_from_raw(name, param.value()) {
+                        Ok(v) => {
+                            adv_params.insert(String::from(name), v);
+                        },
+                        Err(e) => skip(skipped, p, &e),
+                    };
+                }
+            },
+            Command::Other(cmd) => {
+                if cmd.uses_variables() {
+                    skip(skipped, p, "Variables cannot be represented");
+                } else {
+                    skip(skipped, p, "Command has no counterpart in synthetic commands");
+                }
+            },
+        };
+    }
+
+    let chains = compounds.iter()
+        .map(|c| (c.chain.auth_name.clone(), ChainResidues{ first: c.residues[0].auth_number, len: c.residues.len() as i32 }))
+        .collect::<Chains>();
+    let mut double_helices = Vec::new();
+    let mut base_interactions = Vec::new();
+    let mut conformations = Vec::new();
+    let mut mobilizers = Vec::new();
+    for p in deferred {
+        let result = match &p.command {
+            Command::NucleicAcidDuplex{ chain_1, first_residue_1, last_residue_1, chain_2, first_residue_2, last_residue_2 } if !is_density_fit => {
+                double_helix(&chains, (chain_1, *first_residue_1, *last_residue_1), (chain_2, *first_residue_2, *last_residue_2))
+                    .map(|v| double_helices.push(v))
+            },
+            Command::BaseInteraction{ chain_1, residue_1, edge_1, chain_2, residue_2, edge_2, orientation } if !is_density_fit => {
+                base_interaction(&chains, (chain_1, *residue_1, edge_1), (chain_2, *residue_2, edge_2), orientation)
+                    .map(|v| base_interactions.push(v))
+            },
+            Command::NtC{ chain, first_residue, last_residue, ntc: name, weight } => {
+                ntc(&chains, chain, *first_residue, *last_residue, name, *weight).map(|v| conformations.push(v))
+            },
+            Command::Mobilizer{ bond_mobility, scope } => mobilizer(&chains, bond_mobility, scope).map(|v| mobilizers.push(v)),
+            _ => Err("Command has no counterpart in synthetic commands"),
+        };
+        if let Err(reason) = result {
+            skip(skipped, p, reason);
+        }
+    }
+
+    let force_scale_factor = match ntc_force_scale_factor {
+        Some(v) => v,
+        None => {
+            if !conformations.is_empty() {
+                return Err(format!("NtCForceScaleFactor must be set for stage {} because it uses NtC commands", stage));
+            }
+            1.0
+        },
+    };
+    if ntc_force_scale_factor.is_some() && conformations.is_empty() {
+        if let Some(p) = parsed.iter().find(|p| matches!(p.command, Command::NtCForceScaleFactor(_))) {
+            skip(skipped, p, "NtCForceScaleFactor is represented only together with NtC commands");
+        }
+    }
+    let ntcs = api::NtCs{ conformations, force_scale_factor };
+
+    let concrete = if is_density_fit {
+        if !fit_to_density {
+            return Err(format!("Density fit commands of stage {} do not contain fitToDensity", stage));
+        }
+        api::ConcreteCommands::DensityFit(
+            api::DensityFitCommands{
+                structure_file_name: structure_file_name.ok_or(format!("Structure file is not given for stage {}", stage))?,
+                density_map_file_name: density_map_file_name.ok_or(format!("Density map file is not given for stage {}", stage))?,
+                compounds,
+                mobilizers,
+                ntcs,
+                set_default_MD_parameters: set_default_md_parameters,
+            }
+        )
+    } else {
+        api::ConcreteCommands::Standard(
+            api::StandardCommands{
+                compounds,
+                double_helices,
+                base_interactions,
+                ntcs,
+                mobilizers,
+                adv_params,
+                set_default_MD_parameters: set_default_md_parameters,
+            }
+        )
+    };
+
+    Ok(
+        ImportedStage{
+            reporting_interval: reporting_interval.ok_or(format!("reportingInterval is not set for stage {}", stage))?,
+            num_reporting_intervals: num_reporting_intervals.ok_or(format!("numReportingIntervals is not set for stage {}", stage))?,
+            base_interaction_scale_factor: base_interaction_scale_factor.ok_or(format!("baseInteractionScaleFactor is not set for stage {}", stage))?,
+            temperature: temperature.ok_or(format!("temperature is not set for stage {}", stage))?,
+            concrete,
+        }
+    )
+}
+
+/* Converts raw MMB commands to synthetic commands. Lines that synthetic commands
+ * cannot express are left out and reported. */
+pub fn import(raw: &str) -> Result<Imported, String> {
+    let ranges = commands::parse_raw(raw).map_err(|e| format!("Raw commands are invalid, {}", e))?;
+    let parsed = ast::parse(raw);
+
+    let mut skipped = Vec::new();
+    let mut top_level = Vec::new();
+    let mut blocks = HashMap::<i32, Vec<&ParsedCommand>>::new();
+    let mut open_block: Option<(StageCondition, i32)> = None;
+    for p in parsed.iter() {
+        match p.command {
+            Command::ReadBlockStart{ condition, stage } => open_block = Some((condition, stage)),
+            Command::ReadBlockEnd => open_block = None,
+            Command::FirstStage(_) | Command::LastStage(_) if open_block.is_none() => (),
+            _ => match open_block {
+                /* Commands of blocks that apply to several stages become part of each of them */
+                Some((condition, block_stage)) => {
+                    for stage in (ranges.first_stage..=ranges.last_stage).filter(|s| condition.applies(block_stage, *s)) {
+                        blocks.entry(stage).or_default().push(p);
+                    }
+                },
+                None => top_level.push(p),
+            },
+        };
+    }
+
+    let mut stages = Vec::new();
+    if ranges.first_stage == ranges.last_stage {
+        if let Some(mut block) = blocks.remove(&ranges.first_stage) {
+            top_level.append(&mut block);
+        }
+        stages.push(import_stage(&top_level, ranges.first_stage, &mut skipped)?);
+    } else {
+        for p in top_level.iter() {
+            skip(&mut skipped, p, "Commands outside of stage blocks cannot be represented, each stage must have its own commands");
+        }
+        for stage in ranges.first_stage..=ranges.last_stage {
+            let block = match blocks.get(&stage) {
+                Some(v) => v,
+                None => return Err(format!("No stage block applies to stage {}", stage)),
+            };
+            stages.push(import_stage(block, stage, &mut skipped)?);
+        }
+    }
+    skipped.sort_by_key(|s| s.line);
+    /* Lines of blocks that apply to several stages may be skipped once for each stage */
+    skipped.dedup_by_key(|s| s.line);
+
+    let first = stages.remove(0);
+    Ok(
+        Imported{
+            commands: api::Commands{
+                reporting_interval: first.reporting_interval,
+                num_reporting_intervals: first.num_reporting_intervals,
+                stage: ranges.first_stage,
+                base_interaction_scale_factor: first.base_interaction_scale_factor,
+                temperature: first.temperature,
+                concrete: first.concrete,
+                next_stages: stages.into_iter().map(|s| {
+                    api::StageCommands{
+                        reporting_interval: s.reporting_interval,
+                        num_reporting_intervals: s.num_reporting_intervals,
+                        base_interaction_scale_factor: s.base_interaction_scale_factor,
+                        temperature: s.temperature,
+                        concrete: s.concrete,
+                    }
+                }).collect(),
+            },
+            skipped,
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SINGLE_STAGE: &str = "\
+        firstStage 1\n\
+        lastStage 1\n\
+        reportingInterval 0.5\n\
+        numReportingIntervals 20\n\
+        baseInteractionScaleFactor 200\n\
+        temperature 300\n\
+        setDefaultMDParameters\n\
+        RNA A 10 GGCGCUUCGGCGCC\n\
+        nucleicAcidDuplex A 10 13 A 23 20\n\
+        baseInteraction A 14 WatsonCrick A 19 WatsonCrick Cis\n\
+        NtC A 14 15 AA00 2\n\
+        NtCForceScaleFactor 0.5\n\
+        mobilizer Rigid A 10 13\n\
+        PhysicsRadius 0.4\n";
+
+    /* The first stage folds the hairpin, the remaining ones relax it with different temperatures */
+    const MULTI_STAGE: &str = "\
+        firstStage 2\n\
+        lastStage 4\n\
+        readAtStage 2\n\
+        \x20 reportingInterval 1\n\
+        \x20 numReportingIntervals 10\n\
+        \x20 baseInteractionScaleFactor 200\n\
+        \x20 temperature 10\n\
+        \x20 RNA A 1 GGCGCUUCGGCGCC\n\
+        \x20 nucleicAcidDuplex A 1 4 A 14 11\n\
+        readBlockEnd\n\
+        readFromStage 3\n\
+        \x20 reportingInterval 2\n\
+        \x20 numReportingIntervals 5\n\
+        \x20 baseInteractionScaleFactor 100\n\
+        \x20 temperature 300\n\
+        \x20 setDefaultMDParameters\n\
+        \x20 RNA A 1 GGCGCUUCGGCGCC\n\
+        \x20 mobilizer Free\n\
+        readBlockEnd\n";
+
+    fn written_and_imported(commands: &api::Commands, name: &str) -> Imported {
+        let path = std::env::temp_dir().join(format!("raw_import_{}_{}.txt", name, std::process::id()));
+        commands::write(&path, commands).unwrap();
+        let raw = std::fs::read_to_string(&path);
+        let _ = std::fs::remove_file(&path);
+        import(&raw.unwrap()).unwrap()
+    }
+
+    fn assert_round_trip(raw: &str, name: &str) -> api::Commands {
+        let imported = import(raw).unwrap();
+        assert!(imported.skipped.is_empty(), "Skipped lines {:?}", imported.skipped.iter().map(|s| s.line).collect::<Vec<_>>());
+
+        let reimported = written_and_imported(&imported.commands, name);
+        assert!(reimported.skipped.is_empty());
+        assert_eq!(serde_json::to_value(&reimported.commands).unwrap(), serde_json::to_value(&imported.commands).unwrap());
+
+        imported.commands
+    }
+
+    fn stage_commands(concrete: &api::ConcreteCommands) -> &api::StandardCommands {
+        match concrete {
+            api::ConcreteCommands::Standard(v) => v,
+            api::ConcreteCommands::DensityFit(_) => panic!("Expected standard commands"),
+        }
+    }
+
+    #[test]
+    fn single_stage_round_trip() {
+        let commands = assert_round_trip(SINGLE_STAGE, "single");
+        assert_eq!(commands.stage, 1);
+        assert!(commands.next_stages.is_empty());
+
+        let standard = stage_commands(&commands.concrete);
+        assert_eq!(standard.compounds[0].residues[0].auth_number, 10);
+        assert_eq!((standard.double_helices[0].first_res_no_1, standard.double_helices[0].last_res_no_2), (1, 11));
+        assert_eq!((standard.ntcs.conformations[0].first_res_no, standard.ntcs.conformations[0].weight), (5, 2.0));
+        assert_eq!((standard.mobilizers[0].first_residue, standard.mobilizers[0].last_residue), (Some(1), Some(4)));
+        /* Parameter names are spelled as in the schema */
+        assert!(standard.adv_params.contains_key("physicsRadius"));
+    }
+
+    #[test]
+    fn multi_stage_round_trip() {
+        let commands = assert_round_trip(MULTI_STAGE, "multi");
+        assert_eq!(commands.stage, 2);
+        assert_eq!(commands::stage_range(&commands), (2, 4));
+        assert_eq!(commands.temperature, 10.0);
+        assert_eq!(stage_commands(&commands.concrete).double_helices.len(), 1);
+
+        /* readFromStage block applies to both of the remaining stages */
+        assert_eq!(commands.next_stages.len(), 2);
+        for stage in commands.next_stages.iter() {
+            assert_eq!((stage.reporting_interval, stage.num_reporting_intervals, stage.temperature), (2.0, 5, 300.0));
+            let standard = stage_commands(&stage.concrete);
+            assert!(standard.set_default_MD_parameters);
+            assert!(standard.double_helices.is_empty());
+            assert_eq!(standard.mobilizers[0].chain, None);
+        }
+    }
+
+    #[test]
+    fn unrepresentable_lines_are_skipped() {
+        let raw = format!("{}@t 300\ntemperature @t\nconstraint A 10 Weld Ground\n", SINGLE_STAGE);
+        let imported = import(&raw).unwrap();
+        assert_eq!(imported.skipped.iter().map(|s| s.line).collect::<Vec<_>>(), vec![15, 16, 17]);
+        assert_eq!(imported.skipped[0].reason, "Variables cannot be represented");
+    }
+
+    #[test]
+    fn commands_without_a_counterpart_are_refused() {
+        /* Stage 3 has no commands */
+        let raw = MULTI_STAGE.replace("readFromStage 3", "readAtStage 4");
+        assert_eq!(import(&raw).err().unwrap(), "No stage block applies to stage 3");
+
+        /* NtC commands require NtCForceScaleFactor */
+        let raw = SINGLE_STAGE.replace("NtCForceScaleFactor 0.5\n", "");
+        assert!(import(&raw).is_err());
+
+        let raw = SINGLE_STAGE.replace("firstStage 1\n", "");
+        assert!(import(&raw).err().unwrap().starts_with("Raw commands are invalid"));
+    }
+}
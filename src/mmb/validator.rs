@@ -0,0 +1,350 @@
+use std::collections::HashSet;
+
+use crate::mmb::ast::{self, Command, StageCondition, Token, VARIABLE_PREFIX};
+use crate::mmb::vocabulary::{self, CommandSpec};
+
+/* Unknown commands this close to a known one are reported as likely typos */
+const MAX_TYPO_DISTANCE: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+pub struct Diagnostic {
+    pub severity: Severity,
+    /* Line and column are numbered from 1. Problems that concern the commands
+     * as a whole have neither. */
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "line {}, column {}: {}", line, column, self.message),
+            (Some(line), None) => write!(f, "line {}: {}", line, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/* Stage-related values collected while the commands are checked */
+struct StageInfo {
+    first_stage: Option<(i32, usize, usize)>,
+    last_stage: Option<(i32, usize, usize)>,
+    num_reporting_intervals: Option<(i32, usize, usize)>,
+    read_blocks: Vec<(StageCondition, i32, usize, usize)>,
+}
+
+struct Diagnostics {
+    list: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    fn at(&mut self, severity: Severity, line: usize, column: usize, message: String) {
+        self.list.push(Diagnostic{ severity, line: Some(line), column: Some(column), message });
+    }
+
+    fn global(&mut self, severity: Severity, message: String) {
+        self.list.push(Diagnostic{ severity, line: None, column: None, message });
+    }
+}
+
+fn check_command(spec: &CommandSpec, tokens: &[Token], line_no: usize, variables: &HashSet<String>, diags: &mut Diagnostics) {
+    let args = &tokens[1..];
+
+    if !spec.accepts_arity(args.len()) {
+        let column = match args.get(spec.args.len()) {
+            Some(extra) => extra.column,
+            None => tokens[0].column,
+        };
+        diags.at(
+            Severity::Error, line_no, column,
+            format!("Wrong number of arguments for {}, usage is \"{}\"", spec.name, spec.usage())
+        );
+        return;
+    }
+
+    for (idx, token) in args.iter().enumerate() {
+        if token.text.starts_with(VARIABLE_PREFIX) {
+            if !variables.contains(token.text) {
+                diags.at(Severity::Error, line_no, token.column, format!("Variable {} is not defined", token.text));
+            }
+            continue;
+        }
+
+        if let Some(arg_type) = spec.arg_type(idx) {
+            if let Some(msg) = arg_type.check(token.text) {
+                diags.at(Severity::Error, line_no, token.column, format!("{}: {}", spec.name, msg));
+            }
+        }
+    }
+}
+
+fn closest_command(name: &str) -> Option<&'static str> {
+    let lwr = name.to_lowercase();
+
+    vocabulary::names()
+        .map(|known| (known, edit_distance(&lwr, &known.to_lowercase())))
+        .filter(|(_, dist)| *dist <= MAX_TYPO_DISTANCE)
+        .min_by_key(|(_, dist)| *dist)
+        .map(|(known, _)| known)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let subst = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = std::cmp::min(subst, std::cmp::min(prev[j + 1], cur[j]) + 1);
+        }
+        prev = cur;
+    }
+
+    prev[b.len()]
+}
+
+fn record_stage_value(slot: &mut Option<(i32, usize, usize)>, value: i32, name: &str, tokens: &[Token], line_no: usize, warn_duplicate: bool, diags: &mut Diagnostics) {
+    if let Some((_, prev_line, _)) = slot {
+        if !warn_duplicate {
+            return;
+        }
+        diags.at(
+            Severity::Warning, line_no, tokens[0].column,
+            format!("{} was already set on line {}, only the first value is used", name, prev_line)
+        );
+        return;
+    }
+    *slot = Some((value, line_no, tokens[1].column));
+}
+
+fn check_stages(info: &StageInfo, diags: &mut Diagnostics) {
+    let first_stage = match info.first_stage {
+        Some((v, line, column)) => {
+            if v < 1 {
+                diags.at(Severity::Error, line, column, String::from("firstStage must be positive"));
+            }
+            Some(v)
+        },
+        None => {
+            diags.global(Severity::Error, String::from("firstStage was not specified"));
+            None
+        },
+    };
+    let last_stage = match info.last_stage {
+        Some((v, line, column)) => {
+            if first_stage.is_some_and(|first| v < first) {
+                diags.at(Severity::Error, line, column, String::from("lastStage cannot be lower than firstStage"));
+            }
+            Some(v)
+        },
+        None => {
+            diags.global(Severity::Error, String::from("lastStage was not specified"));
+            None
+        },
+    };
+    match info.num_reporting_intervals {
+        Some((v, line, column)) => {
+            if v < 1 {
+                diags.at(Severity::Error, line, column, String::from("numReportingIntervals must be positive"));
+            }
+        },
+        None => diags.global(Severity::Error, String::from("numReportingIntervals was not specified")),
+    };
+
+    if let (Some(first), Some(last)) = (first_stage, last_stage) {
+        for (condition, stage, line, column) in info.read_blocks.iter() {
+            if *stage < first || *stage > last {
+                diags.at(
+                    Severity::Error, *line, *column,
+                    format!("{} refers to stage {} which is outside of the stages {} to {} of the job", condition, stage, first, last)
+                );
+            }
+        }
+    }
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.severity == Severity::Error)
+}
+
+/* Checks raw MMB commands against the known vocabulary. Problems that would make
+ * MMB refuse the commands are errors, suspicious constructs are warnings. */
+pub fn validate(raw: &str) -> Vec<Diagnostic> {
+    let mut diags = Diagnostics{ list: Vec::new() };
+    let mut info = StageInfo{ first_stage: None, last_stage: None, num_reporting_intervals: None, read_blocks: Vec::new() };
+    let mut variables = HashSet::<String>::new();
+    let mut open_block: Option<(StageCondition, i32, usize, usize)> = None;
+
+    for parsed in ast::parse(raw).iter() {
+        let line_no = parsed.line;
+        let tokens = &parsed.tokens;
+
+        let name = tokens[0].text;
+        if name.starts_with(VARIABLE_PREFIX) {
+            if tokens.len() != 2 {
+                diags.at(Severity::Error, line_no, tokens[0].column, format!("Definition of variable {} requires exactly one value", name));
+            } else {
+                variables.insert(String::from(name));
+            }
+            continue;
+        }
+
+        let spec = match vocabulary::lookup(name) {
+            Some(spec) => spec,
+            None => {
+                let msg = match closest_command(name) {
+                    Some(known) => format!("Unknown command {}, did you mean {}?", name, known),
+                    None => format!("Unknown command {}", name),
+                };
+                diags.at(Severity::Warning, line_no, tokens[0].column, msg);
+                continue;
+            },
+        };
+        if spec.name != name {
+            diags.at(
+                Severity::Warning, line_no, tokens[0].column,
+                format!("Command {} is usually written as {}", name, spec.name)
+            );
+        }
+        check_command(spec, tokens, line_no, &variables, &mut diags);
+
+        /* Commands whose arguments are invalid or use variables are not typed and were reported above */
+        match parsed.command {
+            Command::FirstStage(v) => record_stage_value(&mut info.first_stage, v, spec.name, tokens, line_no, true, &mut diags),
+            Command::LastStage(v) => record_stage_value(&mut info.last_stage, v, spec.name, tokens, line_no, true, &mut diags),
+            /* Stage blocks may each set their own value, the first one is what the job reports */
+            Command::NumReportingIntervals(v) => record_stage_value(&mut info.num_reporting_intervals, v, spec.name, tokens, line_no, open_block.is_none(), &mut diags),
+            Command::ReadBlockStart{ condition, stage } => {
+                if let Some((_, _, start_line, _)) = open_block {
+                    diags.at(
+                        Severity::Error, line_no, tokens[0].column,
+                        format!("Stage blocks cannot be nested, block opened on line {} is not closed", start_line)
+                    );
+                }
+                info.read_blocks.push((condition, stage, line_no, tokens[1].column));
+                open_block = Some((condition, stage, line_no, tokens[0].column));
+            },
+            Command::ReadBlockEnd => {
+                if open_block.is_none() {
+                    diags.at(Severity::Error, line_no, tokens[0].column, String::from("readBlockEnd without a matching block start"));
+                }
+                open_block = None;
+            },
+            /* Blocks whose stage is given by a variable cannot be matched against the stage range */
+            Command::Other(_) if StageCondition::from_name(spec.name).is_some() && tokens.len() == 2 && tokens[1].text.starts_with(VARIABLE_PREFIX) => {
+                diags.at(Severity::Error, line_no, tokens[1].column, format!("Stage of {} cannot be given by a variable", spec.name));
+            },
+            Command::NtC{ weight: Some(weight), .. } if weight <= 0.0 => {
+                diags.at(Severity::Error, line_no, tokens[5].column, String::from("NtC weight must be positive"));
+            },
+            _ => (),
+        };
+    }
+
+    if let Some((condition, stage, line, column)) = open_block {
+        diags.at(Severity::Error, line, column, format!("{} block for stage {} is not closed", condition, stage));
+    }
+    check_stages(&info, &mut diags);
+
+    diags.list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "firstStage 1\nlastStage 2\nnumReportingIntervals 10\n";
+
+    fn errors_and_warnings(raw: &str) -> Vec<(Severity, Option<usize>, Option<usize>)> {
+        validate(raw).iter().map(|d| (d.severity, d.line, d.column)).collect()
+    }
+
+    fn single(raw: &str) -> Diagnostic {
+        let mut diags = validate(raw);
+        assert_eq!(diags.len(), 1, "{:?}", diags.iter().map(|d| d.to_string()).collect::<Vec<_>>());
+        diags.remove(0)
+    }
+
+    #[test]
+    fn valid_commands_have_no_diagnostics() {
+        let raw = format!("{}@n 3\nreadAtStage 2\n  baseInteractionScaleFactor @n\nreadBlockEnd\n", HEADER);
+        assert!(validate(&raw).is_empty());
+    }
+
+    #[test]
+    fn arity() {
+        let d = single(&format!("{}temperature 300 400\n", HEADER));
+        assert_eq!(d.severity, Severity::Error);
+        assert_eq!((d.line, d.column), (Some(4), Some(17)));
+        assert!(d.message.starts_with("Wrong number of arguments for temperature"));
+    }
+
+    #[test]
+    fn bad_type() {
+        let d = single(&format!("{}  temperature hot\n", HEADER));
+        assert_eq!(d.severity, Severity::Error);
+        assert_eq!((d.line, d.column), (Some(4), Some(15)));
+        assert!(d.message.starts_with("temperature: Expected"));
+    }
+
+    #[test]
+    fn undefined_variable() {
+        let d = single(&format!("{}temperature @t\n", HEADER));
+        assert_eq!(d.severity, Severity::Error);
+        assert_eq!((d.line, d.column), (Some(4), Some(13)));
+        assert_eq!(d.message, "Variable @t is not defined");
+    }
+
+    #[test]
+    fn typo_suggestion() {
+        let d = single(&format!("{}\ntemprature 300\n", HEADER));
+        assert_eq!(d.severity, Severity::Warning);
+        assert_eq!((d.line, d.column), (Some(5), Some(1)));
+        assert_eq!(d.message, "Unknown command temprature, did you mean temperature?");
+    }
+
+    #[test]
+    fn unclosed_block() {
+        let d = single(&format!("{}readFromStage 2\ntemperature 300\n", HEADER));
+        assert_eq!(d.severity, Severity::Error);
+        assert_eq!((d.line, d.column), (Some(4), Some(1)));
+        assert_eq!(d.message, "readFromStage block for stage 2 is not closed");
+    }
+
+    #[test]
+    fn nested_block() {
+        let raw = format!("{}readAtStage 1\n  readAtStage 2\nreadBlockEnd\n", HEADER);
+        let d = single(&raw);
+        assert_eq!(d.severity, Severity::Error);
+        assert_eq!((d.line, d.column), (Some(5), Some(3)));
+        assert_eq!(d.message, "Stage blocks cannot be nested, block opened on line 4 is not closed");
+    }
+
+    #[test]
+    fn duplicate_first_stage() {
+        let d = single(&format!("{}firstStage 2\n", HEADER));
+        assert_eq!(d.severity, Severity::Warning);
+        assert_eq!((d.line, d.column), (Some(4), Some(1)));
+        assert_eq!(d.message, "firstStage was already set on line 1, only the first value is used");
+    }
+
+    #[test]
+    fn stage_given_by_variable() {
+        let raw = format!("{}@s 2\nreadAtStage @s\nreadBlockEnd\n", HEADER);
+        assert_eq!(errors_and_warnings(&raw)[0], (Severity::Error, Some(5), Some(13)));
+    }
+
+    #[test]
+    fn missing_stage_range() {
+        let diags = validate("temperature 300\n");
+        assert!(has_errors(&diags));
+        assert!(diags.iter().all(|d| d.line.is_none() && d.column.is_none()));
+    }
+}
@@ -0,0 +1,196 @@
+/* Commands understood by MMB and the arguments they take */
+
+use crate::mmb::advanced_params;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgType {
+    Bool,
+    Chain,
+    FileName,
+    Float,
+    Int,
+    /* Residue number, optionally followed by an insertion code */
+    Residue,
+    Sequence,
+    Word,
+    OneOf(&'static [&'static str]),
+}
+
+impl ArgType {
+    /* Returns a description of the problem if the text is not a valid argument of this type */
+    pub fn check(&self, text: &str) -> Option<String> {
+        let ok = match self {
+            ArgType::Bool => ["true", "false", "1", "0"].iter().any(|v| v.eq_ignore_ascii_case(text)),
+            ArgType::Chain | ArgType::Sequence | ArgType::Word => true,
+            ArgType::FileName => {
+                if text.contains('/') || text.contains('\\') {
+                    return Some(format!("File name \"{}\" must not contain path separators", text));
+                }
+                true
+            },
+            ArgType::Float => text.parse::<f64>().is_ok_and(|v| v.is_finite()),
+            ArgType::Int => text.parse::<i64>().is_ok(),
+            ArgType::Residue => {
+                let number = text.strip_suffix(|c: char| c.is_ascii_alphabetic()).unwrap_or(text);
+                number.parse::<i64>().is_ok()
+            },
+            ArgType::OneOf(values) => values.iter().any(|v| v.eq_ignore_ascii_case(text)),
+        };
+
+        if ok {
+            None
+        } else {
+            Some(format!("Expected {} but got \"{}\"", self, text))
+        }
+    }
+}
+
+impl std::fmt::Display for ArgType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgType::Bool => write!(f, "a boolean (True or False)"),
+            ArgType::Chain => write!(f, "a chain name"),
+            ArgType::FileName => write!(f, "a file name"),
+            ArgType::Float => write!(f, "a number"),
+            ArgType::Int => write!(f, "an integer"),
+            ArgType::Residue => write!(f, "a residue number"),
+            ArgType::Sequence => write!(f, "a sequence"),
+            ArgType::Word => write!(f, "a word"),
+            ArgType::OneOf(values) => write!(f, "one of {}", values.join(", ")),
+        }
+    }
+}
+
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub args: &'static [ArgType],
+    /* Number of leading arguments that must be present */
+    pub required: usize,
+    /* Exact argument counts the command accepts. Empty if any count from
+     * "required" up to the number of arguments is accepted. */
+    pub arities: &'static [usize],
+    /* Last argument may be repeated any number of times */
+    pub variadic: bool,
+}
+
+impl CommandSpec {
+    pub fn accepts_arity(&self, count: usize) -> bool {
+        if !self.arities.is_empty() {
+            return self.arities.contains(&count);
+        }
+        count >= self.required && (self.variadic || count <= self.args.len())
+    }
+
+    /* Whether the arguments can be read as this command, arguments that are variables are not */
+    pub fn accepts_args(&self, args: &[&str]) -> bool {
+        self.accepts_arity(args.len()) && args.iter().enumerate().all(|(idx, a)| self.arg_type(idx).is_some_and(|t| t.check(a).is_none()))
+    }
+
+    pub fn arg_type(&self, idx: usize) -> Option<ArgType> {
+        match self.args.get(idx) {
+            Some(t) => Some(*t),
+            None => if self.variadic { self.args.last().copied() } else { None },
+        }
+    }
+
+    pub fn usage(&self) -> String {
+        let mut usage = String::from(self.name);
+        for (idx, arg) in self.args.iter().enumerate() {
+            let name = match arg {
+                ArgType::Bool => "bool",
+                ArgType::Chain => "chain",
+                ArgType::FileName => "file",
+                ArgType::Float => "number",
+                ArgType::Int => "integer",
+                ArgType::Residue => "residue",
+                ArgType::Sequence => "sequence",
+                ArgType::Word | ArgType::OneOf(_) => "word",
+            };
+            if idx < self.required {
+                usage += &format!(" <{}>", name);
+            } else {
+                usage += &format!(" [{}]", name);
+            }
+        }
+        if self.variadic {
+            usage += " ...";
+        }
+        usage
+    }
+}
+
+const BOND_MOBILITIES: &[&str] = &["Rigid", "Torsion", "Free", "Default"];
+
+const fn cmd(name: &'static str, args: &'static [ArgType], required: usize) -> CommandSpec {
+    CommandSpec{ name, args, required, arities: &[], variadic: false }
+}
+
+const fn number(name: &'static str) -> CommandSpec {
+    cmd(name, &[ArgType::Float], 1)
+}
+
+static COMMANDS: &[CommandSpec] = &[
+    /* Stages and reporting */
+    cmd("firstStage", &[ArgType::Int], 1),
+    cmd("lastStage", &[ArgType::Int], 1),
+    cmd("numReportingIntervals", &[ArgType::Int], 1),
+    number("reportingInterval"),
+    cmd("readAtStage", &[ArgType::Int], 1),
+    cmd("readFromStage", &[ArgType::Int], 1),
+    cmd("readToStage", &[ArgType::Int], 1),
+    cmd("readBlockEnd", &[], 0),
+    number("temperature"),
+    number("baseInteractionScaleFactor"),
+
+    /* Molecules */
+    cmd("RNA", &[ArgType::Chain, ArgType::Residue, ArgType::Sequence], 3),
+    cmd("DNA", &[ArgType::Chain, ArgType::Residue, ArgType::Sequence], 3),
+    cmd("Protein", &[ArgType::Chain, ArgType::Residue, ArgType::Sequence], 3),
+    cmd("loadSequencesFromPdb", &[ArgType::FileName], 0),
+
+    /* Interactions and conformations */
+    cmd(
+        "baseInteraction",
+        &[ArgType::Chain, ArgType::Residue, ArgType::Word, ArgType::Chain, ArgType::Residue, ArgType::Word, ArgType::Word],
+        7
+    ),
+    cmd(
+        "nucleicAcidDuplex",
+        &[ArgType::Chain, ArgType::Residue, ArgType::Residue, ArgType::Chain, ArgType::Residue, ArgType::Residue],
+        6
+    ),
+    cmd("NtC", &[ArgType::Chain, ArgType::Residue, ArgType::Residue, ArgType::Word, ArgType::Float], 4),
+    number("NtCForceScaleFactor"),
+    CommandSpec{
+        name: "mobilizer",
+        args: &[ArgType::OneOf(BOND_MOBILITIES), ArgType::Chain, ArgType::Residue, ArgType::Residue],
+        required: 1,
+        arities: &[1, 2, 4],
+        variadic: false,
+    },
+    CommandSpec{ name: "constraint", args: &[ArgType::Word], required: 3, arities: &[], variadic: true },
+    CommandSpec{ name: "contact", args: &[ArgType::Word], required: 3, arities: &[], variadic: true },
+    CommandSpec{ name: "applyContactsWithin", args: &[ArgType::Word], required: 3, arities: &[], variadic: true },
+
+    /* Density fitting */
+    cmd("density", &[ArgType::Word, ArgType::FileName], 2),
+    cmd("fitToDensity", &[], 0),
+
+    /* Defaults */
+    cmd("setDefaultMDParameters", &[], 0),
+    cmd("setDefaultStructureParameters", &[], 0),
+];
+
+/* MMB treats command names case-insensitively */
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.name.eq_ignore_ascii_case(name)).or_else(|| lookup_parameter(name))
+}
+
+/* Commands that set a single parameter of the simulation come from the advanced parameters schema */
+pub fn lookup_parameter(name: &str) -> Option<&'static CommandSpec> {
+    advanced_params::schema().iter().map(|param| &param.command).find(|spec| spec.name.eq_ignore_ascii_case(name))
+}
+
+pub fn names() -> impl Iterator<Item = &'static str> {
+    COMMANDS.iter().map(|spec| spec.name).chain(advanced_params::schema().iter().map(|param| param.name()))
+}
@@ -1,18 +1,29 @@
+mod poller;
+
 use std::process::Command;
 use serde_json;
 
+pub use poller::{job_info, track, untrack};
+
+#[derive(Clone, Copy, PartialEq)]
 pub enum JobState {
     Queued,
     Running,
     Exiting,
     Finished,
     Held,
+    Suspended,
     Unknown,
 }
 
+#[derive(Clone)]
 pub struct JobInfo {
     pub state: JobState,
     pub exec_node: String,
+    /* Following fields are only known once the job has finished */
+    pub exit_status: Option<i32>,
+    pub cpu_time: Option<String>,
+    pub walltime: Option<String>,
 }
 
 pub struct ServerInfo {
@@ -42,7 +53,29 @@ fn get_pbs_state_json() -> Result<JsonObject, String> {
                     Ok(obj) => Ok(obj),
                     Err(e) => Err(e.to_string()),
                 },
-                Err(_) => return Err(String::from("qstat output is not a valid string")),
+                Err(_) => Err(String::from("qstat output is not a valid string")),
+            }
+        },
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/* Queries the job history. This also lists jobs that have already left the queue */
+fn get_pbs_history_json(job_nos: &[u32]) -> Result<JsonObject, String> {
+    let ids: Vec<String> = job_nos.iter().map(|no| no.to_string()).collect();
+
+    match Command::new("qstat")
+                  .args(["-x", "-f", "-F", "json"])
+                  .args(&ids)
+                  .output() {
+        Ok(output) => {
+            /* qstat fails if any of the jobs is not in the history anymore but it still lists the others */
+            match String::from_utf8(output.stdout) {
+                Ok(raw) => match serde_json::from_str::<JsonObject>(raw.as_str()) {
+                    Ok(obj) => Ok(obj),
+                    Err(e) => Err(e.to_string()),
+                },
+                Err(_) => Err(String::from("qstat output is not a valid string")),
             }
         },
         Err(e) => Err(e.to_string()),
@@ -95,6 +128,9 @@ fn parse_job_info(job_no: u32, server_name: &String, pbs_state: &JsonObject) ->
     let mut info = JobInfo{
         state: JobState::Unknown,
         exec_node: String::new(),
+        exit_status: None,
+        cpu_time: None,
+        walltime: None,
     };
 
     let jobs = pbs_state.get("Jobs");
@@ -116,6 +152,7 @@ fn parse_job_info(job_no: u32, server_name: &String, pbs_state: &JsonObject) ->
                 "E" => JobState::Exiting,
                 "F" => JobState::Finished,
                 "H" => JobState::Held,
+                "S" | "U" => JobState::Suspended,
                 _ => return Err(format!("Unknown job state {}", v)),
             },
             Err(e) => return Err(e.to_string()),
@@ -133,18 +170,20 @@ fn parse_job_info(job_no: u32, server_name: &String, pbs_state: &JsonObject) ->
             }
             Err(e) => return Err(format!("Cannot parse exec_node value: {}", e)),
         },
-        None => return Err(String::from("exec_host field not found")),
+        /* Jobs that have not started running yet have no exec_host */
+        None => String::new(),
     };
-
-    Ok(info)
-}
-
-pub fn get_job_info(job_no: u32) -> Result<JobInfo, String> {
-    match get_pbs_state_json() {
-        Ok(state) => match parse_server_info(&state) {
-            Ok(server_info) => parse_job_info(job_no, &server_info.name, &state),
-            Err(e) => Err(e),
+    info.exit_status = match job_obj.get("Exit_status") {
+        Some(v) => match serde_json::from_value::<i32>(v.clone()) {
+            Ok(n) => Some(n),
+            Err(e) => return Err(format!("Cannot parse Exit_status value: {}", e)),
         },
-        Err(e) => Err(e),
+        None => None,
+    };
+    if let Some(used) = job_obj.get("resources_used") {
+        info.cpu_time = used.get("cput").and_then(|v| v.as_str()).map(String::from);
+        info.walltime = used.get("walltime").and_then(|v| v.as_str()).map(String::from);
     }
+
+    Ok(info)
 }
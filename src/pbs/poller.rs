@@ -0,0 +1,167 @@
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::logging;
+use crate::log_plain;
+use super::{get_pbs_history_json, get_pbs_state_json, parse_job_info, parse_server_info, JobInfo, JobState};
+
+const LOGSRC: &str = "pbs_poller";
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+struct PollerState {
+    tracked: HashSet<u32>,
+    cache: HashMap<u32, JobInfo>,
+    /* Tracked jobs that have not been polled yet */
+    pending: HashSet<u32>,
+    /* Set when the last qstat call failed */
+    last_error: Option<String>,
+}
+
+lazy_static! {
+    static ref POLLER: Mutex<PollerState> = Mutex::new(
+        PollerState{
+            tracked: HashSet::new(),
+            cache: HashMap::new(),
+            pending: HashSet::new(),
+            last_error: None,
+        }
+    );
+}
+
+static POLLER_STARTED: Once = Once::new();
+
+fn poll(tracked: &HashSet<u32>) -> Result<HashMap<u32, JobInfo>, String> {
+    let state = get_pbs_state_json()?;
+    let server_info = parse_server_info(&state)?;
+
+    let mut infos = HashMap::new();
+    for job_no in tracked {
+        match parse_job_info(*job_no, &server_info.name, &state) {
+            Ok(info) => {
+                infos.insert(*job_no, info);
+            },
+            Err(e) => log_plain!(Warning, LOGSRC, &format!("Cannot parse status of PBS job {}: {}", job_no, e)),
+        };
+    }
+
+    /* Jobs that have left the queue are looked up in the job history to get their final status */
+    let gone: Vec<u32> = infos.iter().filter(|(_, info)| info.state == JobState::Unknown).map(|(job_no, _)| *job_no).collect();
+    if !gone.is_empty() {
+        match get_pbs_history_json(&gone) {
+            Ok(history) => {
+                for job_no in gone {
+                    match parse_job_info(job_no, &server_info.name, &history) {
+                        Ok(info) => {
+                            infos.insert(job_no, info);
+                        },
+                        Err(e) => log_plain!(Warning, LOGSRC, &format!("Cannot parse history of PBS job {}: {}", job_no, e)),
+                    };
+                }
+            },
+            Err(e) => log_plain!(Warning, LOGSRC, &format!("Cannot query PBS job history: {}", e)),
+        };
+    }
+
+    Ok(infos)
+}
+
+fn spawn_poller() {
+    let interval = Duration::from_secs(config::get().pbs_poll_interval);
+
+    thread::spawn(move || {
+        let mut delay = interval;
+        loop {
+            /* Final status of a finished job does not change, there is no need to query it again */
+            let tracked: HashSet<u32> = {
+                let poller = POLLER.lock().unwrap();
+                poller.tracked.iter()
+                    .filter(|job_no| !matches!(poller.cache.get(job_no), Some(JobInfo{ state: JobState::Finished, .. })))
+                    .copied()
+                    .collect()
+            };
+            if tracked.is_empty() {
+                thread::sleep(interval);
+                continue;
+            }
+
+            /* qstat is run without holding the lock so that readers of the cache are not held up */
+            let started = Instant::now();
+            let result = poll(&tracked);
+
+            let mut poller = POLLER.lock().unwrap();
+            match result {
+                Ok(infos) => {
+                    /* Only jobs tracked when qstat was run are updated. Jobs submitted
+                     * in the meantime may not be in its output yet. */
+                    for job_no in tracked.iter() {
+                        poller.pending.remove(job_no);
+                        match infos.get(job_no) {
+                            Some(info) => {
+                                poller.cache.insert(*job_no, info.clone());
+                            },
+                            None => {
+                                poller.cache.remove(job_no);
+                            },
+                        };
+                    }
+                    poller.last_error = None;
+                    delay = interval;
+                },
+                Err(e) => {
+                    delay = std::cmp::min(delay * 2, MAX_BACKOFF);
+                    log_plain!(Warning, LOGSRC, &format!("Failed to query PBS job status, retrying in {} s: {}", delay.as_secs(), e));
+                    poller.last_error = Some(e);
+                },
+            }
+            drop(poller);
+
+            thread::sleep(delay.saturating_sub(started.elapsed()));
+        }
+    });
+}
+
+/* Returns the last known status of a tracked job */
+pub fn job_info(job_no: u32) -> Result<JobInfo, String> {
+    let poller = POLLER.lock().unwrap();
+
+    match poller.cache.get(&job_no) {
+        Some(info) => Ok(info.clone()),
+        None => {
+            if let Some(e) = &poller.last_error {
+                return Err(e.clone());
+            }
+            if !poller.tracked.contains(&job_no) {
+                return Err(format!("PBS job {} is not tracked", job_no));
+            }
+            if poller.pending.contains(&job_no) {
+                return Err(format!("Status of PBS job {} is not known yet", job_no));
+            }
+            Ok(JobInfo{ state: JobState::Unknown, exec_node: String::new(), exit_status: None, cpu_time: None, walltime: None })
+        },
+    }
+}
+
+/* Starts polling the status of a job. Job that has just been submitted is reported
+ * as queued until it is polled for the first time. */
+pub fn track(job_no: u32, just_submitted: bool) {
+    POLLER_STARTED.call_once(spawn_poller);
+
+    let mut poller = POLLER.lock().unwrap();
+    poller.tracked.insert(job_no);
+    if just_submitted {
+        poller.cache.insert(job_no, JobInfo{ state: JobState::Queued, exec_node: String::new(), exit_status: None, cpu_time: None, walltime: None });
+    } else {
+        poller.pending.insert(job_no);
+    }
+}
+
+pub fn untrack(job_no: u32) {
+    let mut poller = POLLER.lock().unwrap();
+    poller.tracked.remove(&job_no);
+    poller.cache.remove(&job_no);
+    poller.pending.remove(&job_no);
+}
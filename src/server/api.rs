@@ -3,6 +3,12 @@ use rocket::http::Status;
 use serde_derive::{Deserialize, Serialize};
 use serde_json;
 
+pub const DEFAULT_NTC_WEIGHT: f64 = 1.0;
+
+fn default_ntc_weight() -> f64 {
+    DEFAULT_NTC_WEIGHT
+}
+
 /* JSON commands */
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -22,7 +28,9 @@ pub enum CompoundType {
 #[derive(Deserialize, Serialize, Clone)]
 pub enum EdgeInteraction {
     WatsonCrick,
+    Hoogsteen,
     SugarEdge,
+    Bifurcated,
 }
 
 #[derive(Deserialize)]
@@ -44,14 +52,14 @@ pub enum JobCommandsMode {
 #[serde(tag = "mode")]
 pub enum JobCommands {
     None(JobCommandsNone),
-    Synthetic(JobCommandsSynthetic),
+    Synthetic(Box<JobCommandsSynthetic>),
     Raw(JobCommandsRaw),
 }
 
 #[derive(Deserialize)]
 #[serde(tag = "mode")]
 pub enum JobCommandsNotNone {
-    Synthetic(JobCommandsSynthetic),
+    Synthetic(Box<JobCommandsSynthetic>),
     Raw(JobCommandsRaw),
 }
 
@@ -110,6 +118,7 @@ pub struct NtCConformation {
     pub first_res_no: i32,
     pub last_res_no: i32,
     pub ntc: String,
+    #[serde(default = "default_ntc_weight")]
     pub weight: f64,
 }
 
@@ -167,6 +176,21 @@ pub struct Commands {
 
     #[serde(flatten)]
     pub concrete: ConcreteCommands,
+
+    /* Commands of the stages that follow the first one, in order */
+    #[serde(default)]
+    pub next_stages: Vec<StageCommands>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct StageCommands {
+    pub reporting_interval: f64,
+    pub num_reporting_intervals: i32,
+    pub base_interaction_scale_factor: i32,
+    pub temperature: f64,
+
+    #[serde(flatten)]
+    pub concrete: ConcreteCommands,
 }
 
 /* Requests */
@@ -180,7 +204,10 @@ pub struct ApiRequestData {
 #[serde(tag = "req_type")]
 pub enum ApiRequest {
     StartJob(ApiRequestData),
+    ResumeJob(ApiRequestData),
     StopJob(ApiRequestData),
+    SuspendJob(ApiRequestData),
+    ContinueJob(ApiRequestData),
     CreateJob(ApiRequestData),
     DeleteJob(ApiRequestData),
     JobStatus(ApiRequestData),
@@ -188,11 +215,18 @@ pub enum ApiRequest {
     JobCommands(ApiRequestData),
     SessionInfo(ApiRequestData),
     MmbOutput(ApiRequestData),
+    JobConsoleOutput(ApiRequestData),
     CloneJob(ApiRequestData),
     ListExamples(ApiRequestData),
     ActivateExample(ApiRequestData),
     FileOperation(ApiRequestData),
     ListAdditionalFiles(ApiRequestData),
+    ListRunnerProfiles(ApiRequestData),
+    ValidateCommands(ApiRequestData),
+    ImportRawCommands(ApiRequestData),
+    ConvertJobToSynthetic(ApiRequestData),
+    ListInteractionClasses(ApiRequestData),
+    AdvancedParametersSchema(ApiRequestData),
 }
 
 #[derive(Deserialize)]
@@ -223,6 +257,8 @@ pub struct CloneJobRqData {
 #[derive(Deserialize)]
 pub struct CreateJobRqData {
     pub name: String,
+    #[serde(default)]
+    pub profile: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -230,16 +266,38 @@ pub struct SimpleJobRqData {
     pub id: String,
 }
 
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PbsResources {
+    #[serde(default)]
+    pub walltime: Option<u64>,
+    #[serde(default)]
+    pub ncpus: Option<u32>,
+    #[serde(default)]
+    pub mem: Option<u64>,
+    #[serde(default)]
+    pub queue: Option<String>,
+}
+
 #[derive(Deserialize)]
 pub struct ResumeJobRqData {
     pub id: String,
     pub commands: Commands,
+    #[serde(default)]
+    pub pbs_resources: Option<PbsResources>,
+    #[serde(default)]
+    pub timeout: Option<u64>,
 }
 
 #[derive(Deserialize)]
 pub struct StartJobRqData {
     pub id: String,
     pub commands: JobCommandsNotNone,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub pbs_resources: Option<PbsResources>,
+    #[serde(default)]
+    pub timeout: Option<u64>,
 }
 
 #[derive(Deserialize)]
@@ -248,6 +306,16 @@ pub struct StartJobRawRqData {
     pub commands: String,
 }
 
+#[derive(Deserialize)]
+pub struct ValidateCommandsRqData {
+    pub commands: String,
+}
+
+#[derive(Deserialize)]
+pub struct ImportRawCommandsRqData {
+    pub commands: String,
+}
+
 #[derive(Deserialize)]
 pub struct FileOperationRqData {
     pub req_type: FileOperationRequestType,
@@ -269,15 +337,90 @@ pub struct AuthResponse {
     pub message: String,
 }
 
+#[derive(Serialize)]
+pub enum FailureReason {
+    CpuTimeLimitExceeded,
+    MemoryLimitExceeded,
+    WalltimeLimitExceeded,
+    NonZeroExitStatus,
+    KilledBySignal,
+    ExecutionFailed,
+    TimedOut,
+    DiskQuotaExceeded,
+}
+
+#[derive(Serialize)]
+pub struct RunSummary {
+    pub exit_status: Option<i32>,
+    pub signal: Option<i32>,
+    pub exec_host: Option<String>,
+    pub cpu_time: Option<String>,
+    pub walltime: Option<String>,
+}
+
 #[derive(Serialize)]
 pub enum JobState {
+    Unknown,
     NotStarted,
     Queued,
     Running,
+    Stopping,
+    Held,
+    Suspended,
+    Cancelled,
     Finished,
     Failed,
 }
 
+#[derive(Serialize)]
+pub enum AdvancedParameterType {
+    Bool,
+    Int,
+    Float,
+    Choice,
+    Text,
+}
+
+#[derive(Serialize)]
+pub struct AdvancedParameterInfo {
+    pub name: String,
+    pub value_type: AdvancedParameterType,
+    pub choices: Vec<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /* Null if MMB's own default applies */
+    pub default: serde_json::Value,
+    pub description: String,
+}
+
+pub type AdvancedParametersSchema = Vec<AdvancedParameterInfo>;
+
+#[derive(Serialize)]
+pub struct CommandDiagnostic {
+    pub severity: DiagnosticSeverity,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct CommandsValidation {
+    pub is_valid: bool,
+    pub diagnostics: Vec<CommandDiagnostic>,
+}
+
+#[derive(Serialize)]
+pub struct ConsoleOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+#[derive(Serialize)]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+}
+
 #[derive(Serialize)]
 pub struct ErrorResponse {
     pub success: bool,
@@ -327,6 +470,19 @@ pub struct JobCommandsRaw {
     pub commands: String,
 }
 
+#[derive(Serialize)]
+pub struct ImportedCommands {
+    pub commands: Commands,
+    pub unrepresentable: Vec<UnrepresentableLine>,
+}
+
+#[derive(Serialize)]
+pub struct InteractionClasses {
+    pub ntcs: Vec<String>,
+    pub edges: Vec<String>,
+    pub orientations: Vec<String>,
+}
+
 #[derive(Serialize)]
 pub struct JobCreated {
     pub id: String,
@@ -338,9 +494,13 @@ pub struct JobInfo {
     pub name: String,
     pub created_on: String,
     pub commands_mode: JobCommandsMode,
+    pub runner_profile: String,
+    pub timeout: Option<u64>,
     pub first_stage: i32,
     pub last_stage: i32,
     pub state: JobState,
+    pub failure_reason: Option<FailureReason>,
+    pub run_summary: Option<RunSummary>,
     pub progress: Option<JobProgress>,
 }
 
@@ -348,11 +508,41 @@ pub struct JobInfo {
 pub struct JobProgress {
     pub step: String,
     pub total_steps: i32,
+    pub stage: i32,
+    pub first_run_stage: i32,
+    pub last_run_stage: i32,
 }
 
 pub type JobList = Vec<JobInfo>;
 
+#[derive(Serialize)]
+pub enum RunnerBackend {
+    Local,
+    Pbs,
+    Slurm,
+}
+
+#[derive(Serialize)]
+pub struct RunnerProfileInfo {
+    pub name: String,
+    pub backend: RunnerBackend,
+    pub description: String,
+    pub is_default: bool,
+}
+
+pub type RunnerProfileList = Vec<RunnerProfileInfo>;
+
 #[derive(Serialize)]
 pub struct SessionInfo {
     pub id: String,
+    pub disk_usage: String,
+    pub disk_quota: Option<String>,
+    pub job_disk_quota: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct UnrepresentableLine {
+    pub line: usize,
+    pub text: String,
+    pub reason: String,
 }
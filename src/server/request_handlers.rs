@@ -4,6 +4,7 @@ use rocket::http::Status;
 use serde_json;
 use uuid::Uuid;
 
+use crate::config;
 use crate::{mmb, session::uuid_to_str};
 use crate::session;
 use crate::session::JobError;
@@ -20,15 +21,31 @@ fn job_info_to_api(id: &Uuid, info: session::job::JobInfo) -> api::JobInfo {
         id: session::uuid_to_str(id),
         name: info.name,
         state: mmb_state_to_job_state(info.state),
+        failure_reason: info.failure_reason.map(failure_reason_to_api),
+        run_summary: match info.run_summary {
+            Some(summary) => Some(api::RunSummary{
+                exit_status: summary.exit_status,
+                signal: summary.signal,
+                exec_host: summary.exec_host,
+                cpu_time: summary.cpu_time,
+                walltime: summary.walltime,
+            }),
+            None => None,
+        },
         first_stage: info.first_stage,
         last_stage: info.last_stage,
         created_on: info.created_on.to_string(),
         commands_mode: info.commands_mode,
+        runner_profile: info.runner_profile,
+        timeout: info.timeout,
         progress: match info.progress {
             Some(progress) => {
                 Some(api::JobProgress{
                     step: step_to_str(progress.step),
                     total_steps: progress.total_steps,
+                    stage: progress.stage,
+                    first_run_stage: progress.first_run_stage,
+                    last_run_stage: progress.last_run_stage,
                 })
             },
             None => None,
@@ -36,6 +53,19 @@ fn job_info_to_api(id: &Uuid, info: session::job::JobInfo) -> api::JobInfo {
     }
 }
 
+fn failure_reason_to_api(reason: session::FailureReason) -> api::FailureReason {
+    match reason {
+        session::FailureReason::CpuTimeLimitExceeded => api::FailureReason::CpuTimeLimitExceeded,
+        session::FailureReason::MemoryLimitExceeded => api::FailureReason::MemoryLimitExceeded,
+        session::FailureReason::WalltimeLimitExceeded => api::FailureReason::WalltimeLimitExceeded,
+        session::FailureReason::NonZeroExitStatus => api::FailureReason::NonZeroExitStatus,
+        session::FailureReason::KilledBySignal => api::FailureReason::KilledBySignal,
+        session::FailureReason::ExecutionFailed => api::FailureReason::ExecutionFailed,
+        session::FailureReason::TimedOut => api::FailureReason::TimedOut,
+        session::FailureReason::DiskQuotaExceeded => api::FailureReason::DiskQuotaExceeded,
+    }
+}
+
 fn handle_simple_rq_data(data: serde_json::Value) -> Result<Uuid, String> {
     let parsed: serde_json::Result<api::SimpleJobRqData> = serde_json::from_value(data);
     if parsed.is_err() {
@@ -54,9 +84,13 @@ fn mmb_state_to_job_state(s: mmb::State) -> api::JobState {
         mmb::State::NotStarted => api::JobState::NotStarted,
         mmb::State::Queued => api::JobState::Queued,
         mmb::State::Running => api::JobState::Running,
+        mmb::State::Stopping => api::JobState::Stopping,
+        mmb::State::Held => api::JobState::Held,
+        mmb::State::Suspended => api::JobState::Suspended,
+        mmb::State::Cancelled => api::JobState::Cancelled,
         mmb::State::Finished => api::JobState::Finished,
         mmb::State::Failed => api::JobState::Failed,
-        mmb::State::Unknown => panic!("mmb::State::Unknown shall never be returned"),
+        mmb::State::Unknown => api::JobState::Unknown,
     }
 }
 
@@ -83,7 +117,7 @@ pub fn activate_example(session: Arc<Session>, data: serde_json::Value, path: Pa
         Err(e) => return ApiResponse::fail(Status::InternalServerError, e.to_string()),
     };
 
-    match session.create_job(parsed.id, Some(cmds_json), None) {
+    match session.create_job(parsed.id, Some(cmds_json), None, None) {
         Ok(id) => {
             let resp = api::JobCreated{id: session::uuid_to_str(&id)};
             ApiResponse::ok(serde_json::to_value(resp).unwrap())
@@ -95,6 +129,35 @@ pub fn activate_example(session: Arc<Session>, data: serde_json::Value, path: Pa
     }
 }
 
+pub fn advanced_parameters_schema() -> ApiResponse {
+    let schema: api::AdvancedParametersSchema = mmb::advanced_params::schema().iter().map(|spec| {
+        let (value_type, choices) = match spec.value_type() {
+            mmb::advanced_params::ArgType::Bool => (api::AdvancedParameterType::Bool, Vec::new()),
+            mmb::advanced_params::ArgType::Int => (api::AdvancedParameterType::Int, Vec::new()),
+            mmb::advanced_params::ArgType::Float => (api::AdvancedParameterType::Float, Vec::new()),
+            mmb::advanced_params::ArgType::OneOf(choices) => (api::AdvancedParameterType::Choice, choices.iter().map(|c| String::from(*c)).collect()),
+            _ => (api::AdvancedParameterType::Text, Vec::new()),
+        };
+        api::AdvancedParameterInfo{
+            name: String::from(spec.name()),
+            value_type,
+            choices,
+            min: spec.min,
+            max: spec.max,
+            default: match spec.default {
+                Some(mmb::advanced_params::ParamValue::Bool(v)) => serde_json::Value::from(v),
+                Some(mmb::advanced_params::ParamValue::Int(v)) => serde_json::Value::from(v),
+                Some(mmb::advanced_params::ParamValue::Float(v)) => serde_json::Value::from(v),
+                Some(mmb::advanced_params::ParamValue::Text(v)) => serde_json::Value::from(v),
+                None => serde_json::Value::Null,
+            },
+            description: String::from(spec.description),
+        }
+    }).collect();
+
+    ApiResponse::ok(serde_json::to_value(schema).unwrap())
+}
+
 pub fn clone_job(session: Arc<Session>, data: serde_json::Value) -> ApiResponse {
     let parsed = match serde_json::from_value::<api::CloneJobRqData>(data) {
         Ok(v) => v,
@@ -125,6 +188,21 @@ pub fn clone_job(session: Arc<Session>, data: serde_json::Value) -> ApiResponse
     }
 }
 
+pub fn convert_job_to_synthetic(session: Arc<Session>, data: serde_json::Value) -> ApiResponse {
+    let id = match handle_simple_rq_data(data) {
+        Ok(id) => id,
+        Err(e) => return ApiResponse::fail(Status::BadRequest, e),
+    };
+
+    match session.convert_job_to_synthetic(&id) {
+        Ok(()) => ApiResponse::ok(serde_json::to_value(EMPTY).unwrap()),
+        Err(e) => match e {
+            JobError::BadInput(msg) => ApiResponse::fail(Status::BadRequest, msg),
+            JobError::InternalError => ApiResponse::fail(Status::InternalServerError, String::from(INTR_SERV_ERR)),
+        },
+    }
+}
+
 pub fn create_job(session: Arc<Session>, data: serde_json::Value) -> ApiResponse {
     let parsed = match serde_json::from_value::<api::CreateJobRqData>(data) {
         Ok(v) => v,
@@ -139,7 +217,7 @@ pub fn create_job(session: Arc<Session>, data: serde_json::Value) -> ApiResponse
         return ApiResponse::fail(Status::BadRequest, String::from("Job must have a name"));
     }
 
-    match session.create_job(parsed.name, None, None) {
+    match session.create_job(parsed.name, None, None, parsed.profile) {
         Ok(id) => {
             let resp = api::JobCreated{id: session::uuid_to_str(&id)};
             ApiResponse::ok(serde_json::to_value(resp).unwrap())
@@ -226,6 +304,25 @@ pub fn file_operation(session: Arc<Session>, data: serde_json::Value) -> ApiResp
     }
 }
 
+pub fn import_raw_commands(data: serde_json::Value) -> ApiResponse {
+    let parsed: serde_json::Result<api::ImportRawCommandsRqData> = serde_json::from_value(data);
+    let import_data = match parsed {
+        Ok(data) => data,
+        Err(_) => return ApiResponse::fail(Status::BadRequest, String::from("Invalid import raw commands request")),
+    };
+
+    match mmb::raw_import::import(&import_data.commands) {
+        Ok(imported) => {
+            let resp = api::ImportedCommands{
+                commands: imported.commands,
+                unrepresentable: imported.skipped.into_iter().map(|s| api::UnrepresentableLine{ line: s.line, text: s.text, reason: s.reason }).collect(),
+            };
+            ApiResponse::ok(serde_json::to_value(resp).unwrap())
+        },
+        Err(e) => ApiResponse::fail(Status::BadRequest, e),
+    }
+}
+
 pub fn list_additional_files(session: Arc<Session>, data: serde_json::Value) -> ApiResponse {
     let id = match handle_simple_rq_data(data) {
         Ok(id) => id,
@@ -261,6 +358,18 @@ pub fn list_examples(path: PathBuf) -> ApiResponse {
     ApiResponse::ok(serde_json::to_value(resp_list).unwrap())
 }
 
+pub fn list_interaction_classes() -> ApiResponse {
+    let classes = mmb::parameters::get();
+
+    let list = api::InteractionClasses{
+        ntcs: classes.ntcs.iter().cloned().collect(),
+        edges: classes.edges.iter().cloned().collect(),
+        orientations: classes.orientations.iter().cloned().collect(),
+    };
+
+    ApiResponse::ok(serde_json::to_value(list).unwrap())
+}
+
 pub fn list_jobs(session: Arc<Session>) -> ApiResponse {
     let list = session.list_jobs();
 
@@ -275,6 +384,27 @@ pub fn list_jobs(session: Arc<Session>) -> ApiResponse {
     ApiResponse::ok(serde_json::to_value(jobs).unwrap())
 }
 
+pub fn list_runner_profiles() -> ApiResponse {
+    let cfg = config::get();
+    let default_profile = cfg.default_runner_profile();
+
+    let mut list: api::RunnerProfileList = cfg.runner_profiles().into_iter().map(|(name, profile)| {
+        api::RunnerProfileInfo{
+            is_default: name == default_profile,
+            name,
+            backend: match profile.backend {
+                config::RunnerBackend::Local => api::RunnerBackend::Local,
+                config::RunnerBackend::Pbs => api::RunnerBackend::Pbs,
+                config::RunnerBackend::Slurm => api::RunnerBackend::Slurm,
+            },
+            description: profile.description,
+        }
+    }).collect();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+
+    ApiResponse::ok(serde_json::to_value(list).unwrap())
+}
+
 pub fn job_commands(session: Arc<Session>, data: serde_json::Value) -> ApiResponse {
     let id = match handle_simple_rq_data(data) {
         Ok(id) => id,
@@ -295,7 +425,7 @@ pub fn job_commands(session: Arc<Session>, data: serde_json::Value) -> ApiRespon
             match session.job_commands(id) {
                 Ok(commands) => match commands {
                     Some(commands) => {
-                        let resp = api::JobCommands::Synthetic(api::JobCommandsSynthetic{ commands });
+                        let resp = api::JobCommands::Synthetic(Box::new(api::JobCommandsSynthetic{ commands }));
                         ApiResponse::ok(serde_json::to_value(resp).unwrap())
                     },
                     None => ApiResponse::fail(Status::InternalServerError, String::from(NO_CMDS)),
@@ -348,10 +478,38 @@ pub fn mmb_output(session: Arc<Session>, data: serde_json::Value) -> ApiResponse
     }
 }
 
+pub fn job_console_output(session: Arc<Session>, data: serde_json::Value) -> ApiResponse {
+    let id = match handle_simple_rq_data(data) {
+        Ok(id) => id,
+        Err(e) => return ApiResponse::fail(Status::BadRequest, e),
+    };
+
+    match session.job_console_output(&id) {
+        Some(ret) => match ret {
+            Ok(output) => {
+                let resp = api::ConsoleOutput{ stdout: output.stdout, stderr: output.stderr };
+                ApiResponse::ok(serde_json::to_value(resp).unwrap())
+            },
+            Err(e) => ApiResponse::fail(Status::InternalServerError, e),
+        },
+        None => ApiResponse::fail(Status::BadRequest, String::from("Unknown job id")),
+    }
+}
+
 pub fn session_info(session: Arc<Session>) -> ApiResponse {
     let id = session::uuid_to_str(&session.id());
+    let disk_usage = match session.disk_usage() {
+        Ok(v) => v.to_string(),
+        Err(e) => return ApiResponse::fail(Status::InternalServerError, e),
+    };
 
-    let info = api::SessionInfo{ id };
+    let cfg = config::get();
+    let info = api::SessionInfo{
+        id,
+        disk_usage,
+        disk_quota: cfg.session_quota.map(|v| v.to_string()),
+        job_disk_quota: cfg.job_quota.map(|v| v.to_string()),
+    };
     ApiResponse::ok(serde_json::to_value(info).unwrap())
 }
 
@@ -369,7 +527,7 @@ pub fn start_job(session: Arc<Session>, data: serde_json::Value) -> ApiResponse
 
     match start_data.commands {
         api::JobCommandsNotNone::Synthetic(commands) => {
-            match session.start_job(&id, commands.commands) {
+            match session.start_job(&id, commands.commands, start_data.profile, start_data.pbs_resources, start_data.timeout) {
                 Ok(()) => ApiResponse::ok(serde_json::to_value(EMPTY).unwrap()),
                 Err(e) => match e {
                     JobError::BadInput(msg) => ApiResponse::fail(Status::BadRequest, msg),
@@ -378,7 +536,7 @@ pub fn start_job(session: Arc<Session>, data: serde_json::Value) -> ApiResponse
             }
         },
         api::JobCommandsNotNone::Raw(commands) => {
-            match session.start_job_raw(&id, commands.commands) {
+            match session.start_job_raw(&id, commands.commands, start_data.profile, start_data.pbs_resources, start_data.timeout) {
                 Ok(()) => ApiResponse::ok(serde_json::to_value(EMPTY).unwrap()),
                 Err(e) => match e {
                     JobError::BadInput(msg) => ApiResponse::fail(Status::BadRequest, msg),
@@ -389,6 +547,27 @@ pub fn start_job(session: Arc<Session>, data: serde_json::Value) -> ApiResponse
     }
 }
 
+pub fn resume_job(session: Arc<Session>, data: serde_json::Value) -> ApiResponse {
+    let parsed: serde_json::Result<api::ResumeJobRqData> = serde_json::from_value(data);
+    let resume_data = match parsed {
+        Ok(data) => data,
+        Err(_) => return ApiResponse::fail(Status::BadRequest, String::from("Invalid resume job request")),
+    };
+
+    let id = match Uuid::parse_str(&resume_data.id) {
+        Ok(v) => v,
+        Err(_) => return ApiResponse::fail(Status::BadRequest, String::from("Malformed job id")),
+    };
+
+    match session.resume_job(&id, resume_data.commands, resume_data.pbs_resources, resume_data.timeout) {
+        Ok(()) => ApiResponse::ok(serde_json::to_value(EMPTY).unwrap()),
+        Err(e) => match e {
+            JobError::BadInput(msg) => ApiResponse::fail(Status::BadRequest, msg),
+            JobError::InternalError => ApiResponse::fail(Status::InternalServerError, String::from(INTR_SERV_ERR)),
+        },
+    }
+}
+
 pub fn stop_job(session: Arc<Session>, data: serde_json::Value) -> ApiResponse {
     let id = match handle_simple_rq_data(data) {
         Ok(id) => id,
@@ -400,3 +579,53 @@ pub fn stop_job(session: Arc<Session>, data: serde_json::Value) -> ApiResponse {
         Err(e) => ApiResponse::fail(Status::BadRequest, e),
     }
 }
+
+pub fn suspend_job(session: Arc<Session>, data: serde_json::Value) -> ApiResponse {
+    let id = match handle_simple_rq_data(data) {
+        Ok(id) => id,
+        Err(e) => return ApiResponse::fail(Status::BadRequest, e),
+    };
+
+    match session.suspend_job(id) {
+        Ok(_) => ApiResponse::ok(serde_json::to_value(EMPTY).unwrap()),
+        Err(e) => ApiResponse::fail(Status::BadRequest, e),
+    }
+}
+
+pub fn continue_job(session: Arc<Session>, data: serde_json::Value) -> ApiResponse {
+    let id = match handle_simple_rq_data(data) {
+        Ok(id) => id,
+        Err(e) => return ApiResponse::fail(Status::BadRequest, e),
+    };
+
+    match session.continue_job(id) {
+        Ok(_) => ApiResponse::ok(serde_json::to_value(EMPTY).unwrap()),
+        Err(e) => ApiResponse::fail(Status::BadRequest, e),
+    }
+}
+
+pub fn validate_commands(data: serde_json::Value) -> ApiResponse {
+    let parsed: serde_json::Result<api::ValidateCommandsRqData> = serde_json::from_value(data);
+    let validate_data = match parsed {
+        Ok(data) => data,
+        Err(_) => return ApiResponse::fail(Status::BadRequest, String::from("Invalid validate commands request")),
+    };
+
+    let diagnostics = mmb::validator::validate(&validate_data.commands);
+    let validation = api::CommandsValidation{
+        is_valid: !mmb::validator::has_errors(&diagnostics),
+        diagnostics: diagnostics.into_iter().map(|d| {
+            api::CommandDiagnostic{
+                severity: match d.severity {
+                    mmb::validator::Severity::Error => api::DiagnosticSeverity::Error,
+                    mmb::validator::Severity::Warning => api::DiagnosticSeverity::Warning,
+                },
+                line: d.line,
+                column: d.column,
+                message: d.message,
+            }
+        }).collect(),
+    };
+
+    ApiResponse::ok(serde_json::to_value(validation).unwrap())
+}
@@ -13,12 +13,12 @@ use crate::server::api as srvapi;
 use crate::server::{request_handlers, session_cookie, transfer_handlers, LOGSRC};
 use crate::server::responders::{DensityFile, PdbFile, WMSError};
 use crate::session::session::Session;
-use crate::session::session_manager::SessionManager;
+use crate::session::session_manager::{self, SessionManager};
 
 use super::incoming::{IncomingAuthRequest, IncomingApiRequest};
 
 struct AppState {
-    pub sm: RwLock<SessionManager>,
+    pub sm: Arc<RwLock<SessionManager>>,
     pub jobs_dir: PathBuf,
     pub examples_dir: PathBuf,
     pub domain: String,
@@ -47,10 +47,12 @@ impl fmt::Display for srvapi::ApiRequest {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let s = apirequest_text!(
             self,
-            StopJob, StartJob, CreateJob, DeleteJob,
+            StopJob, StartJob, ResumeJob, SuspendJob, ContinueJob, CreateJob, DeleteJob,
             JobStatus, ListJobs, JobCommands, SessionInfo,
-            MmbOutput, CloneJob, ListExamples, ActivateExample,
-            FileOperation, ListAdditionalFiles
+            MmbOutput, JobConsoleOutput, CloneJob, ListExamples, ActivateExample,
+            FileOperation, ListAdditionalFiles, ListRunnerProfiles, ValidateCommands,
+            ImportRawCommands, ConvertJobToSynthetic, ListInteractionClasses,
+            AdvancedParametersSchema
         );
 
         write!(f, "{}", s)
@@ -104,7 +106,10 @@ fn get_session_authorized(jar: &rocket::http::CookieJar<'_>, state: &AppState) -
     match get_session(jar, state) {
         Some(s) => {
             match s.is_logged_in() {
-                true => Some(s),
+                true => {
+                    s.touch();
+                    Some(s)
+                },
                 false => None,
             }
         },
@@ -159,6 +164,7 @@ fn auth_verify(auth: IncomingAuthRequest, jar: &rocket::http::CookieJar<'_>, sta
                         let c = session_cookie::make_auth_cookie(state.domain.clone(), session::uuid_to_str(&id), state.require_https);
                         jar.add_private(c);
                         session.set_login_state(true);
+                        session.touch();
                         srvapi::AuthResponse{ status: rocket::http::Status::Ok, message: String::new() }
                     },
                     None => srvapi::AuthResponse{status: rocket::http::Status::BadRequest, message: String::from("No such session")},
@@ -254,19 +260,29 @@ fn api(data: IncomingApiRequest, jar: &rocket::http::CookieJar<'_>, state: &rock
 
     match data.payload {
         srvapi::ApiRequest::StartJob(v) => Ok(request_handlers::start_job(s, v.data)),
+        srvapi::ApiRequest::ResumeJob(v) => Ok(request_handlers::resume_job(s, v.data)),
         srvapi::ApiRequest::StopJob(v) => Ok(request_handlers::stop_job(s, v.data)),
+        srvapi::ApiRequest::SuspendJob(v) => Ok(request_handlers::suspend_job(s, v.data)),
+        srvapi::ApiRequest::ContinueJob(v) => Ok(request_handlers::continue_job(s, v.data)),
         srvapi::ApiRequest::CreateJob(v) => Ok(request_handlers::create_job(s, v.data)),
         srvapi::ApiRequest::DeleteJob(v) => Ok(request_handlers::delete_job(s, v.data)),
         srvapi::ApiRequest::CloneJob(v) => Ok(request_handlers::clone_job(s, v.data)),
         srvapi::ApiRequest::JobStatus(v) => Ok(request_handlers::job_status(s, v.data)),
         srvapi::ApiRequest::ListJobs(_) => Ok(request_handlers::list_jobs(s)),
         srvapi::ApiRequest::MmbOutput(v) => Ok(request_handlers::mmb_output(s, v.data)),
+        srvapi::ApiRequest::JobConsoleOutput(v) => Ok(request_handlers::job_console_output(s, v.data)),
         srvapi::ApiRequest::JobCommands(v) => Ok(request_handlers::job_commands(s, v.data)),
         srvapi::ApiRequest::SessionInfo(_) => Ok(request_handlers::session_info(s)),
         srvapi::ApiRequest::ListExamples(_) => Ok(request_handlers::list_examples(state.examples_dir.clone())),
         srvapi::ApiRequest::ActivateExample(v) => Ok(request_handlers::activate_example(s, v.data, state.examples_dir.clone())),
         srvapi::ApiRequest::FileOperation(v) => Ok(request_handlers::file_operation(s, v.data)),
         srvapi::ApiRequest::ListAdditionalFiles(v) => Ok(request_handlers::list_additional_files(s, v.data)),
+        srvapi::ApiRequest::ListRunnerProfiles(_) => Ok(request_handlers::list_runner_profiles()),
+        srvapi::ApiRequest::ValidateCommands(v) => Ok(request_handlers::validate_commands(v.data)),
+        srvapi::ApiRequest::ImportRawCommands(v) => Ok(request_handlers::import_raw_commands(v.data)),
+        srvapi::ApiRequest::ConvertJobToSynthetic(v) => Ok(request_handlers::convert_job_to_synthetic(s, v.data)),
+        srvapi::ApiRequest::ListInteractionClasses(_) => Ok(request_handlers::list_interaction_classes()),
+        srvapi::ApiRequest::AdvancedParametersSchema(_) => Ok(request_handlers::advanced_parameters_schema()),
     }
 }
 
@@ -369,6 +385,14 @@ pub fn start() -> rocket::Rocket<rocket::Build> {
         }
     };
 
+    let mut sm = SessionManager::create();
+    sm.restore_sessions();
+
+    let sm = Arc::new(RwLock::new(sm));
+    session_manager::spawn_sweeper(sm.clone());
+    session_manager::spawn_enforcer(sm.clone());
+    session::spawn_dispatcher();
+
     rocket::custom(srv_cfg)
         .mount("/",
                routes![
@@ -386,7 +410,7 @@ pub fn start() -> rocket::Rocket<rocket::Build> {
                ]
         )
         .manage(AppState{
-            sm: RwLock::new(SessionManager::create()),
+            sm,
             jobs_dir: PathBuf::from(cfg.jobs_dir.as_str()),
             examples_dir: PathBuf::from(cfg.examples_dir.as_str()),
             domain: cfg.domain.clone(),
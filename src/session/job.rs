@@ -1,22 +1,27 @@
 use std::{collections::HashMap, io::Write};
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime};
 use file_lock::FileLock;
 use uuid::Uuid;
 
-use crate::config;
+use crate::config::{self, PbsResources, RunnerBackend, RunnerProfile};
 use crate::logging;
 use crate::log_plain;
 use crate::mmb;
 use crate::server::api;
 
-use super::job_runner::JobRunner;
+use super::job_runner::{self, FailureReason, JobRunner, RunnerManifest, RunSummary};
 use super::local_job_runner::LocalJobRunner;
+use super::manifest;
 use super::pbs_job_runner::PbsJobRunner;
+use super::slurm_job_runner::SlurmJobRunner;
+use super::disk_usage;
 use super::JobError;
 
 const LOGSRC: &'static str = "job";
+/* Only the end of the console output of a job is returned, MMB can be very talkative */
+const CONSOLE_OUTPUT_MAX_SIZE: u64 = 64 * 1024;
 
 #[derive(Clone)]
 struct AdditionalFileInternal {
@@ -34,6 +39,7 @@ struct Progress {
     pub state: mmb::State,
     pub step: i32,
     pub total_steps: i32,
+    pub stage: Option<i32>,
 }
 
 pub struct AdditionalFile {
@@ -41,14 +47,23 @@ pub struct AdditionalFile {
     pub size: u64,
 }
 
+pub struct ConsoleOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
+
 #[derive(Clone)]
 pub struct JobInfo {
     pub name: String,
     pub state: mmb::State,
+    pub failure_reason: Option<FailureReason>,
+    pub run_summary: Option<RunSummary>,
     pub first_stage: i32,
     pub last_stage: i32,
     pub created_on: u128,
     pub commands_mode: api::JobCommandsMode,
+    pub runner_profile: String,
+    pub timeout: Option<u64>,
     pub progress: Option<JobProgress>,
 }
 
@@ -56,6 +71,10 @@ pub struct JobInfo {
 pub struct JobProgress {
     pub step: i32,
     pub total_steps: i32,
+    /* Stage that is being computed and the stages the current run covers */
+    pub stage: i32,
+    pub first_run_stage: i32,
+    pub last_run_stage: i32,
 }
 
 pub struct Job {
@@ -67,6 +86,27 @@ pub struct Job {
     diag_file_path: PathBuf,
     progress_file_path: PathBuf,
     runner: Box<dyn JobRunner + Send + Sync>,
+    runner_profile: String,
+    last_executor_state: mmb::State,
+    /* Set when the user stops the job, cleared when the job is started again */
+    cancelled: bool,
+    /* PBS resources requested for the current run */
+    pbs_resources: Option<api::PbsResources>,
+    /* Maximum runtime of the current run in seconds */
+    timeout: Option<u64>,
+    /* When the executor has been first seen running the current run */
+    run_started_on: Option<SystemTime>,
+    /* Time the current run has spent suspended by the user, this does not count towards the timeout */
+    suspended_for: Duration,
+    suspended_on: Option<SystemTime>,
+    timed_out: bool,
+    /* Set when the job has been stopped because its session or the job itself exceeded the disk quota */
+    quota_exceeded: bool,
+    /* PBS walltime of the current run has been lowered to the timeout */
+    walltime_from_timeout: bool,
+    /* Size of the job directory as of the last refresh and bytes uploaded since then */
+    cached_disk_usage: u64,
+    uploaded_since_refresh: u64,
     created_on: SystemTime,
     file_transfers: HashMap<Uuid, FileTransfer>,
     additional_files: HashMap<String, AdditionalFileInternal>,
@@ -164,7 +204,8 @@ fn copy_job_dir(tgt: &PathBuf, src: &PathBuf) -> Result<(), String> {
         };
 
         if name == mmb::PGRS_FILE_NAME ||
-           name == mmb::DOUT_FILE_NAME {
+           name == mmb::DOUT_FILE_NAME ||
+           name == manifest::JOB_MANIFEST_FILE_NAME {
             continue;
         }
 
@@ -251,23 +292,96 @@ fn mk_progress_file_path(mut base_path: PathBuf) -> PathBuf {
     base_path
 }
 
-fn mk_runner() -> Result<Box<dyn JobRunner + Sync + Send>, String> {
-    if config::get().use_pbs_offloading {
-        let runner = match PbsJobRunner::create() {
-            Ok(runner) => runner,
-            Err(e) => return Err(e),
-        };
-        Ok(Box::new(runner))
-    } else {
-        let runner = match LocalJobRunner::create() {
-            Ok(runner) => runner,
-            Err(e) => return Err(e),
-        };
-        Ok(Box::new(runner))
+fn mk_runner(profile: &RunnerProfile, pbs_resources: PbsResources, time_limit: Option<u64>) -> Result<Box<dyn JobRunner + Sync + Send>, String> {
+    match profile.backend {
+        RunnerBackend::Local => {
+            let runner = LocalJobRunner::create()?;
+            Ok(Box::new(runner))
+        },
+        RunnerBackend::Pbs => {
+            let runner = PbsJobRunner::create(profile.queue.clone(), pbs_resources)?;
+            Ok(Box::new(runner))
+        },
+        RunnerBackend::Slurm => {
+            let runner = SlurmJobRunner::create(profile.queue.clone(), time_limit)?;
+            Ok(Box::new(runner))
+        },
+    }
+}
+
+fn mk_runner_from_manifest(runner_manifest: &RunnerManifest, job_dir: &Path) -> Result<Box<dyn JobRunner + Sync + Send>, String> {
+    match runner_manifest {
+        RunnerManifest::Local{ process, .. } => {
+            let runner = LocalJobRunner::restore(process.clone(), job_dir)?;
+            Ok(Box::new(runner))
+        },
+        RunnerManifest::Pbs{ job_no, queue } => {
+            let runner = PbsJobRunner::restore(*job_no, queue.clone(), job_dir)?;
+            Ok(Box::new(runner))
+        },
+        RunnerManifest::Slurm{ job_id, partition, held } => {
+            let runner = SlurmJobRunner::restore(*job_id, partition.clone(), *held)?;
+            Ok(Box::new(runner))
+        },
     }
 }
 
-fn read_diagnostics(path: &Path) -> Result<String, String> {
+fn resolve_runner_profile(name: &str) -> Result<RunnerProfile, JobError> {
+    match config::get().runner_profile(name) {
+        Some(profile) => Ok(profile),
+        None => Err(JobError::BadInput(format!("Unknown runner profile {}", name))),
+    }
+}
+
+/* Combines the PBS resources requested for a job with the server-wide defaults.
+ * Queue requested for the job replaces the queue of the profile. */
+fn resolve_pbs_resources(profile: &mut RunnerProfile, requested: Option<api::PbsResources>) -> Result<PbsResources, JobError> {
+    let cfg = config::get();
+
+    let requested = match requested {
+        Some(v) => v,
+        None => return Ok(cfg.pbs_default_resources.clone()),
+    };
+    if profile.backend != RunnerBackend::Pbs {
+        return Err(JobError::BadInput(String::from("PBS resources can only be requested for jobs run through PBS")));
+    }
+
+    let resources = PbsResources{
+        walltime: requested.walltime,
+        ncpus: requested.ncpus,
+        mem: requested.mem,
+    };
+    if let Err(e) = cfg.pbs_max_resources.check_request(&resources) {
+        return Err(JobError::BadInput(e));
+    }
+
+    if let Some(queue) = requested.queue {
+        if let Some(allowed) = &cfg.pbs_allowed_queues {
+            if !allowed.contains(&queue) {
+                return Err(JobError::BadInput(format!("PBS queue {} is not allowed", queue)));
+            }
+        }
+        profile.queue = Some(queue);
+    }
+
+    Ok(cfg.pbs_default_resources.merged(&resources))
+}
+
+/* Timeout requested for a job may not exceed the server-wide maximum */
+fn resolve_timeout(requested: Option<u64>) -> Result<Option<u64>, JobError> {
+    let cfg = config::get();
+
+    match requested {
+        Some(0) => Err(JobError::BadInput(String::from("Timeout cannot be zero"))),
+        Some(secs) => match cfg.job_max_timeout {
+            Some(max) if secs > max => Err(JobError::BadInput(format!("Requested timeout of {} s exceeds the maximum of {} s", secs, max))),
+            _ => Ok(Some(secs)),
+        },
+        None => Ok(cfg.job_default_timeout.or(cfg.job_max_timeout)),
+    }
+}
+
+fn read_output_file(path: &Path) -> Result<String, String> {
     if !path.is_file() {
         return Ok(String::new());
     }
@@ -284,6 +398,40 @@ fn read_diagnostics(path: &Path) -> Result<String, String> {
     }
 }
 
+/* Reads at most the last max_size bytes of the file, starting at a line boundary if the file is longer */
+fn read_output_file_tail(path: &Path, max_size: u64) -> Result<String, String> {
+    if !path.is_file() {
+        return Ok(String::new());
+    }
+
+    let mut fh = match std::fs::File::open(path) {
+        Ok(fh) => fh,
+        Err(e) => return Err(e.to_string()),
+    };
+    let size = match fh.metadata() {
+        Ok(md) => md.len(),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let truncated = size > max_size;
+    if truncated {
+        if let Err(e) = fh.seek(SeekFrom::Start(size - max_size)) {
+            return Err(e.to_string());
+        }
+    }
+
+    let mut buf = Vec::new();
+    if let Err(e) = fh.take(max_size).read_to_end(&mut buf) {
+        return Err(e.to_string());
+    }
+
+    let start = match truncated {
+        true => buf.iter().position(|&b| b == b'\n').map_or(0, |pos| pos + 1),
+        false => 0,
+    };
+    Ok(String::from_utf8_lossy(&buf[start..]).into_owned())
+}
+
 fn read_mmb_progress(path: &Path) -> Result<Option<Progress>, String> {
     /* If the progress file does not exist, it could mean that MMB just has not created it yet */
     if !path.is_file() {
@@ -319,6 +467,7 @@ fn read_mmb_progress(path: &Path) -> Result<Option<Progress>, String> {
             state: progress.state,
             step: progress.step,
             total_steps: progress.total_steps,
+            stage: progress.stage,
         })),
         Err(e) => Err(format!("Cannot parse progress file: {}", e)),
     }
@@ -335,6 +484,11 @@ pub fn remove_file(path: &Path) -> Result<(), String> {
 }
 
 impl Job {
+    /* Stops the job without recording why, for jobs that are about to be deleted */
+    pub fn abort(&mut self) -> Result<(), String> {
+        self.runner.stop()
+    }
+
     pub fn available_stages(&self) -> Vec<i32> {
         get_stages(&self.job_dir, mmb::TRAJECTORY_FILE_PREFIX)
     }
@@ -350,7 +504,7 @@ impl Job {
     }
 
     pub fn check_retire(&mut self) -> Result<mmb::State, String> {
-        self.runner.executor_state()
+        self.executor_state()
     }
 
     pub fn clone(name: String, job_dir: PathBuf, src: &Job) -> Result<Job, JobError> {
@@ -370,7 +524,8 @@ impl Job {
         let diag_file_path = mk_diag_file_path(job_dir.clone());
         let progress_file_path = mk_progress_file_path(job_dir.clone());
 
-        let runner = match mk_runner() {
+        let profile = resolve_runner_profile(&src.runner_profile)?;
+        let runner = match mk_runner(&profile, config::get().pbs_default_resources.clone(), None) {
             Ok(runner) => runner,
             Err(e) => {
                 log_plain!(Error, LOGSRC, &format!("Failed to create runner for cloned job {}: {}", job_dir.to_str().unwrap_or(logging::INV_FILE_PATH), e));
@@ -378,7 +533,7 @@ impl Job {
             }
         };
 
-        Ok(Job{
+        let job = Job{
             name,
             commands: src.commands.clone(),
             raw_commands: src.raw_commands.clone(),
@@ -387,11 +542,33 @@ impl Job {
             diag_file_path,
             progress_file_path,
             runner,
+            runner_profile: src.runner_profile.clone(),
+            last_executor_state: mmb::State::Unknown,
+            cancelled: false,
+            pbs_resources: None,
+            timeout: None,
+            run_started_on: None,
+            suspended_for: Duration::ZERO,
+            suspended_on: None,
+            timed_out: false,
+            quota_exceeded: false,
+            walltime_from_timeout: false,
+            cached_disk_usage: src.disk_usage(),
+            uploaded_since_refresh: 0,
             created_on: SystemTime::now(),
             file_transfers: HashMap::new(),
             additional_files: src.additional_files.clone(),
             file_transfer_timeout: src.file_transfer_timeout.clone(),
-        })
+        };
+
+        match job.save_manifest() {
+            Ok(()) => Ok(job),
+            Err(e) => {
+                log_plain!(Error, LOGSRC, &format!("Failed to write manifest for cloned job {}: {}", job.job_dir.to_str().unwrap_or(logging::INV_FILE_PATH), e));
+                job.delete();
+                Err(JobError::InternalError)
+            },
+        }
     }
 
     pub fn commands(&self) -> Option<api::Commands> {
@@ -415,14 +592,52 @@ impl Job {
         self.raw_commands.clone()
     }
 
-    pub fn create(name: String, job_dir: PathBuf, commands: Option<api::Commands>, raw_commands: Option<String>) -> Result<Job, JobError> {
+    /* Switches a job from raw to synthetic commands. Raw commands that synthetic commands
+     * cannot express would be lost, such jobs are not converted. */
+    pub fn convert_to_synthetic(&mut self) -> Result<(), JobError> {
+        if let Ok(info) = self.info() {
+            if info.state.is_active() {
+                return Err(JobError::BadInput(String::from("Job cannot be converted while it is running")));
+            }
+        }
+
+        let raw_commands = match &self.raw_commands {
+            Some(v) => v,
+            None => return Err(JobError::BadInput(String::from("Only jobs in raw commands mode can be converted to synthetic commands"))),
+        };
+        let imported = match mmb::raw_import::import(raw_commands) {
+            Ok(v) => v,
+            Err(e) => return Err(JobError::BadInput(e)),
+        };
+        if let Some(first) = imported.skipped.first() {
+            return Err(JobError::BadInput(format!(
+                "Raw commands cannot be converted without loss, {} line(s) cannot be represented, first is line {}: {}",
+                imported.skipped.len(), first.line, first.reason
+            )));
+        }
+
+        if let Err(e) = mmb::commands::validate(&imported.commands) {
+            return Err(JobError::BadInput(format!("Converted commands are invalid, {}", e)));
+        }
+
+        self.commands = Some(imported.commands);
+        self.raw_commands = None;
+        self.update_manifest();
+
+        Ok(())
+    }
+
+    pub fn create(name: String, job_dir: PathBuf, commands: Option<api::Commands>, raw_commands: Option<String>, runner_profile: Option<String>) -> Result<Job, JobError> {
         assert!(!(commands.is_some() && raw_commands.is_some()), "Synthetic and raw commands cannot be both specified at the same time");
 
+        let runner_profile = runner_profile.unwrap_or_else(|| config::get().default_runner_profile());
+        let profile = resolve_runner_profile(&runner_profile)?;
+
         let cmds_file_path = mk_cmds_file_path(job_dir.clone());
         let diag_file_path = mk_diag_file_path(job_dir.clone());
         let progress_file_path = mk_progress_file_path(job_dir.clone());
 
-        let runner = match mk_runner() {
+        let runner = match mk_runner(&profile, config::get().pbs_default_resources.clone(), None) {
             Ok(runner) => runner,
             Err(e) => {
                 log_plain!(Error, LOGSRC, &format!("Failed to create runner for new job {}: {}", job_dir.to_str().unwrap_or(logging::INV_FILE_PATH), e));
@@ -430,7 +645,7 @@ impl Job {
             },
         };
 
-        Ok(Job{
+        let job = Job{
             name,
             commands,
             raw_commands,
@@ -439,11 +654,33 @@ impl Job {
             diag_file_path,
             progress_file_path,
             runner,
+            runner_profile,
+            last_executor_state: mmb::State::Unknown,
+            cancelled: false,
+            pbs_resources: None,
+            timeout: None,
+            run_started_on: None,
+            suspended_for: Duration::ZERO,
+            suspended_on: None,
+            timed_out: false,
+            quota_exceeded: false,
+            walltime_from_timeout: false,
+            cached_disk_usage: 0,
+            uploaded_since_refresh: 0,
             created_on: std::time::SystemTime::now(),
             file_transfers: HashMap::new(),
             additional_files: HashMap::new(),
             file_transfer_timeout: Duration::new(30, 0),
-        })
+        };
+
+        match job.save_manifest() {
+            Ok(()) => Ok(job),
+            Err(e) => {
+                log_plain!(Error, LOGSRC, &format!("Failed to write manifest for new job {}: {}", job.job_dir.to_str().unwrap_or(logging::INV_FILE_PATH), e));
+                job.delete();
+                Err(JobError::InternalError)
+            },
+        }
     }
 
     pub fn density_map_file_name(&self) -> Option<String> {
@@ -456,24 +693,83 @@ impl Job {
         }
     }
 
+    /* Deletes the job directory. Jobs that are merely dropped leave their data on disk
+     * so that they can be restored on the next startup. */
+    pub fn delete(mut self) {
+        let xfr_ids: Vec<Uuid> = self.file_transfers.keys().copied().collect();
+        for id in xfr_ids {
+            self.terminate_transfer(&id);
+        }
+
+        if let Err(e) = std::fs::remove_dir_all(&self.job_dir) {
+            log_plain!(Error, LOGSRC, &format!("Cannot delete job directory {}: {}", &self.job_dir.to_str().unwrap_or(logging::INV_FILE_PATH), e));
+        }
+    }
+
     pub fn delete_additional_file(&mut self, file_name: String) -> Result<(), String> {
         match self.additional_files.remove(&file_name) {
             Some(_) => {
                 self.delete_file(&file_name);
+                self.update_manifest();
                 Ok(())
             },
             None => Err(String::from("No such file")),
         }
     }
 
+    /* Does not walk the job directory, the estimate is only as fresh as the last set_disk_usage() */
+    pub fn disk_usage(&self) -> u64 {
+        self.cached_disk_usage + self.uploaded_since_refresh
+    }
+
+    pub fn console_output(&self) -> Result<ConsoleOutput, String> {
+        Ok(ConsoleOutput{
+            stdout: read_output_file_tail(job_runner::mk_stdout_file_path(self.job_dir.clone()).as_path(), CONSOLE_OUTPUT_MAX_SIZE)?,
+            stderr: read_output_file_tail(job_runner::mk_stderr_file_path(self.job_dir.clone()).as_path(), CONSOLE_OUTPUT_MAX_SIZE)?,
+        })
+    }
+
     pub fn diagnostics(&mut self) -> Result<String, String> {
-        read_diagnostics(self.diag_file_path.as_path())
+        read_output_file(self.diag_file_path.as_path())
     }
 
     pub fn dir(&self) -> PathBuf {
         self.job_dir.clone()
     }
 
+    /* Stops the job if it has been running for longer than its timeout. Returns true if the job has been stopped */
+    pub fn enforce_timeout(&mut self) -> Result<bool, String> {
+        if self.timed_out {
+            return Ok(false);
+        }
+        let timeout = match self.timeout {
+            Some(v) => Duration::from_secs(v),
+            None => return Ok(false),
+        };
+
+        if self.executor_state()? != mmb::State::Running {
+            return Ok(false);
+        }
+        let now = SystemTime::now();
+        let suspended_for = match self.suspended_on {
+            Some(suspended) => self.suspended_for + now.duration_since(suspended).unwrap_or_default(),
+            None => self.suspended_for,
+        };
+        let elapsed = match self.run_started_on {
+            Some(started) => now.duration_since(started).unwrap_or_default().saturating_sub(suspended_for),
+            None => return Ok(false),
+        };
+        if elapsed < timeout {
+            return Ok(false);
+        }
+
+        self.runner.stop()?;
+        self.timed_out = true;
+        self.update_manifest();
+
+        Ok(true)
+    }
+
     pub fn finish_upload(&mut self, id: Uuid) -> Result<(), String> {
         if !self.file_transfers.contains_key(&id) {
             return Err(String::from("No such transfer"));
@@ -485,6 +781,7 @@ impl Job {
                 match xfr.fh.metadata() {
                     Ok(m) => {
                         self.additional_files.insert(xfr.file_name, AdditionalFileInternal{size: m.len()});
+                        self.update_manifest();
                         Ok(())
                     },
                     Err(e) => {
@@ -497,7 +794,7 @@ impl Job {
     }
 
     pub fn info(&mut self) -> Result<JobInfo, String> {
-        let executor_state = self.runner.executor_state()?;
+        let executor_state = self.executor_state()?;
         let maybe_progress = read_mmb_progress(self.progress_file_path.as_path())?;
 
         let avail_stages = self.available_stages();
@@ -523,7 +820,11 @@ impl Job {
         match maybe_progress {
             Some(progress) => {
                 let reported_state = {
-                    if executor_state == mmb::State::Running {
+                    if executor_state == mmb::State::Stopping || executor_state == mmb::State::Cancelled ||
+                        executor_state == mmb::State::Held || executor_state == mmb::State::Suspended {
+                        /* Executor knows better than MMB what is going on with the job */
+                        executor_state
+                    } else if executor_state == mmb::State::Running {
                         /* MMB reports the job has finished but the MMB process is still running
                            Wait until the MMB process actually terminates */
                         mmb::State::Running
@@ -536,18 +837,20 @@ impl Job {
                         progress.state
                     }
                 };
+                let reported_state = self.final_state(reported_state);
 
                 Ok(JobInfo{
                     name: self.name.clone(),
                     state: reported_state,
+                    failure_reason: self.failure_reason(reported_state),
+                    run_summary: self.run_summary(reported_state),
                     first_stage,
                     last_stage,
                     created_on: self.created_on.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis(),
                     commands_mode: self.commands_mode(),
-                    progress: Some(JobProgress{
-                        step: progress.step,
-                        total_steps: progress.total_steps,
-                    }),
+                    runner_profile: self.runner_profile.clone(),
+                    timeout: self.timeout,
+                    progress: Some(self.job_progress(&progress)),
                 })
             },
             None => {
@@ -556,14 +859,19 @@ impl Job {
                     } else {
                         executor_state
                 };
+                let reported_state = self.final_state(reported_state);
 
                 Ok(JobInfo{
                     name: self.name.clone(),
                     state: reported_state,
+                    failure_reason: self.failure_reason(reported_state),
+                    run_summary: self.run_summary(reported_state),
                     first_stage,
                     last_stage,
                     created_on: self.created_on.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis(),
                     commands_mode: self.commands_mode(),
+                    runner_profile: self.runner_profile.clone(),
+                    timeout: self.timeout,
                     progress: None,
                 })
             },
@@ -587,6 +895,13 @@ impl Job {
             return Err(String::from("Filename is reserved"));
         }
 
+        if let Some(quota) = config::get().job_quota {
+            let usage = self.disk_usage();
+            if usage >= quota {
+                return Err(format!("Disk quota of the job is exhausted ({} of {} bytes used)", usage, quota));
+            }
+        }
+
         let mut path = self.job_dir.clone();
         path.push(&file_name);
 
@@ -608,6 +923,43 @@ impl Job {
         Ok(id)
     }
 
+    /* Stages the current run of the job covers */
+    fn run_stages(&self) -> (i32, i32) {
+        if let Some(commands) = &self.commands {
+            return mmb::commands::stage_range(commands);
+        }
+        if let Some(raw_commands) = &self.raw_commands {
+            if let Ok(parsed) = mmb::commands::parse_raw(raw_commands) {
+                return (parsed.first_stage, parsed.last_stage);
+            }
+        }
+        (0, 0)
+    }
+
+    fn job_progress(&self, progress: &Progress) -> JobProgress {
+        let (first_run_stage, last_run_stage) = self.run_stages();
+
+        /* If MMB does not report the stage, the stage being computed is the first one
+         * of the run whose last frame has not been written yet */
+        let stage = match progress.stage {
+            Some(stage) => stage,
+            None => {
+                let finished = get_stages(&self.job_dir, mmb::LAST_FRAME_FILE_PREFIX);
+                (first_run_stage..=last_run_stage)
+                    .find(|stage| !finished.contains(stage))
+                    .unwrap_or(last_run_stage)
+            },
+        };
+
+        JobProgress{
+            step: progress.step,
+            total_steps: progress.total_steps,
+            stage,
+            first_run_stage,
+            last_run_stage,
+        }
+    }
+
     pub fn last_available_stage(&self) -> Option<i32> {
         match get_stages(&self.job_dir, mmb::TRAJECTORY_FILE_PREFIX).last() {
             Some(v) => Some(*v),
@@ -619,9 +971,97 @@ impl Job {
         self.additional_files.iter().map(|(k, v)| { AdditionalFile{name: k.clone(), size: v.size} }).collect()
     }
 
-    pub fn start(&mut self, commands: api::Commands) -> Result<(), JobError> {
+
+    pub fn restore(job_dir: PathBuf) -> Result<Job, String> {
+        let job_manifest = match manifest::read::<manifest::JobManifest>(&manifest::mk_job_manifest_path(job_dir.clone())) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Cannot read job manifest: {}", e)),
+        };
+
+        if job_manifest.commands.is_some() && job_manifest.raw_commands.is_some() {
+            return Err(String::from("Job manifest specifies both synthetic and raw commands"));
+        }
+
+        let runner = match mk_runner_from_manifest(&job_manifest.runner, &job_dir) {
+            Ok(runner) => runner,
+            Err(e) => return Err(format!("Cannot restore runner: {}", e)),
+        };
+
+        let mut additional_files = HashMap::new();
+        for file in job_manifest.additional_files {
+            let mut path = job_dir.clone();
+            path.push(&file.name);
+            if !path.is_file() {
+                log_plain!(Warning, LOGSRC, &format!("Additional file {} of restored job is missing", path.to_str().unwrap_or(logging::INV_FILE_PATH)));
+                continue;
+            }
+            additional_files.insert(file.name, AdditionalFileInternal{size: file.size});
+        }
+
+        let cached_disk_usage = match disk_usage(&job_dir) {
+            Ok(v) => v,
+            Err(e) => {
+                log_plain!(Warning, LOGSRC, &format!("Cannot determine disk usage of restored job {}: {}", job_dir.to_str().unwrap_or(logging::INV_FILE_PATH), e));
+                0
+            },
+        };
+
+        let was_queued = match &job_manifest.runner {
+            RunnerManifest::Local{ queued, .. } => *queued,
+            _ => false,
+        };
+
+        let mut job = Job{
+            name: job_manifest.name,
+            commands: job_manifest.commands,
+            raw_commands: job_manifest.raw_commands,
+            cmds_file_path: mk_cmds_file_path(job_dir.clone()),
+            diag_file_path: mk_diag_file_path(job_dir.clone()),
+            progress_file_path: mk_progress_file_path(job_dir.clone()),
+            job_dir,
+            runner,
+            runner_profile: job_manifest.runner_profile.unwrap_or_else(|| config::get().default_runner_profile()),
+            last_executor_state: mmb::State::Unknown,
+            cancelled: job_manifest.cancelled,
+            pbs_resources: job_manifest.pbs_resources,
+            timeout: job_manifest.timeout,
+            run_started_on: job_manifest.run_started_on,
+            suspended_for: job_manifest.suspended_for,
+            suspended_on: job_manifest.suspended_on,
+            timed_out: job_manifest.timed_out,
+            quota_exceeded: job_manifest.quota_exceeded,
+            walltime_from_timeout: job_manifest.walltime_from_timeout,
+            cached_disk_usage,
+            uploaded_since_refresh: 0,
+            created_on: job_manifest.created_on,
+            file_transfers: HashMap::new(),
+            additional_files,
+            file_transfer_timeout: Duration::new(30, 0),
+        };
+
+        /* Job that was still waiting in the local queue is put back in the queue.
+         * If MMB has left a progress file behind, the job has been started before
+         * the server went down and it must not be run again. */
+        if was_queued && !job.progress_file_path.is_file() {
+            log_plain!(Info, LOGSRC, &format!("Requeueing job {}", job.job_dir.to_str().unwrap_or(logging::INV_FILE_PATH)));
+            if let Err(e) = job.runner.start(job.job_dir.clone(), job.cmds_file_path.as_path(), job.diag_file_path.as_path(), job.progress_file_path.as_path()) {
+                log_plain!(Error, LOGSRC, &format!("Failed to requeue job {}: {}", job.job_dir.to_str().unwrap_or(logging::INV_FILE_PATH), e));
+            }
+        }
+
+        Ok(job)
+    }
+
+    /* Usage is determined by walking the job directory while the job is not locked. Bytes uploaded
+     * before the walk started are included in it, bytes uploaded since then are still counted separately. */
+    pub fn set_disk_usage(&mut self, usage: u64, uploaded_before_walk: u64) {
+        self.cached_disk_usage = usage;
+        self.uploaded_since_refresh = self.uploaded_since_refresh.saturating_sub(uploaded_before_walk);
+    }
+
+    pub fn start(&mut self, commands: api::Commands, runner_profile: Option<String>, pbs_resources: Option<api::PbsResources>, timeout: Option<u64>) -> Result<(), JobError> {
         if let Ok(info) = self.info() {
-            if info.state == mmb::State::Running {
+            if info.state.is_active() {
                 return Err(JobError::BadInput(String::from("Job is already running")));
             }
         }
@@ -629,19 +1069,39 @@ impl Job {
         if self.raw_commands.is_some() {
             return Err(JobError::BadInput(String::from("Job created in raw commands mode cannot be run in synthetic commands mode")));
         }
+        if let Err(e) = mmb::commands::validate(&commands) {
+            return Err(JobError::BadInput(e));
+        }
+
+        let timeout = resolve_timeout(timeout)?;
+        let (runner_profile, runner, walltime_from_timeout) = self.prepare_runner(runner_profile, pbs_resources.clone(), timeout)?;
 
         self.commands = Some(commands);
 
         if let Err(_) = self.prune_job_dir(self.commands.as_ref().unwrap().stage) {
             return Err(JobError::InternalError);
         }
+        self.runner = runner;
+        self.runner_profile = runner_profile;
+        self.cancelled = false;
+        self.pbs_resources = pbs_resources;
+        self.timeout = timeout;
+        self.run_started_on = None;
+        self.suspended_for = Duration::ZERO;
+        self.suspended_on = None;
+        self.timed_out = false;
+        self.quota_exceeded = false;
+        self.walltime_from_timeout = walltime_from_timeout;
         if let Err(e) = mmb::commands::write(&self.cmds_file_path, self.commands.as_ref().unwrap()) {
             log_plain!(Error, LOGSRC, &format!("Failed to write job commands file {}: {}", &self.cmds_file_path.to_str().unwrap_or(logging::INV_FILE_PATH), e));
             return Err(JobError::InternalError);
         }
 
         match self.runner.start(self.job_dir.clone(), self.cmds_file_path.as_path(), self.diag_file_path.as_path(), self.progress_file_path.as_path()) {
-            Ok(()) => Ok(()),
+            Ok(()) => {
+                self.update_manifest();
+                Ok(())
+            },
             Err(e) => {
                 log_plain!(Error, LOGSRC, &format!("JobRunner failed to start job {}: {}", &self.job_dir.to_str().unwrap_or(logging::INV_FILE_PATH), e));
                 Err(JobError::InternalError)
@@ -649,9 +1109,9 @@ impl Job {
         }
     }
 
-    pub fn start_raw(&mut self, raw_commands: String) -> Result<(), JobError> {
+    pub fn start_raw(&mut self, raw_commands: String, runner_profile: Option<String>, pbs_resources: Option<api::PbsResources>, timeout: Option<u64>) -> Result<(), JobError> {
         if let Ok(info) = self.info() {
-            if info.state == mmb::State::Running {
+            if info.state.is_active() {
                 return Err(JobError::BadInput(String::from("Job is already running")));
             }
         }
@@ -662,12 +1122,26 @@ impl Job {
 
         let parsed = match mmb::commands::parse_raw(&raw_commands) {
             Ok(v) => v,
-            Err(e) => return Err(JobError::BadInput(String::from("Raw commands are invalid"))),
+            Err(e) => return Err(JobError::BadInput(format!("Raw commands are invalid, {}", e))),
         };
 
+        let timeout = resolve_timeout(timeout)?;
+        let (runner_profile, runner, walltime_from_timeout) = self.prepare_runner(runner_profile, pbs_resources.clone(), timeout)?;
+
         if let Err(_) = self.prune_job_dir(parsed.first_stage) {
             return Err(JobError::InternalError);
         }
+        self.runner = runner;
+        self.runner_profile = runner_profile;
+        self.cancelled = false;
+        self.pbs_resources = pbs_resources;
+        self.timeout = timeout;
+        self.run_started_on = None;
+        self.suspended_for = Duration::ZERO;
+        self.suspended_on = None;
+        self.timed_out = false;
+        self.quota_exceeded = false;
+        self.walltime_from_timeout = walltime_from_timeout;
         if let Err(e) = mmb::commands::write_raw(&self.cmds_file_path, &raw_commands) {
             log_plain!(Error, LOGSRC, &format!("Failed to write raw job commands file {}: {}", &self.cmds_file_path.to_str().unwrap_or(logging::INV_FILE_PATH), e.to_string()));
             return Err(JobError::InternalError);
@@ -675,7 +1149,10 @@ impl Job {
         self.raw_commands = Some(raw_commands);
 
         match self.runner.start(self.job_dir.clone(), self.cmds_file_path.as_path(), self.diag_file_path.as_path(), self.progress_file_path.as_path()) {
-            Ok(()) => Ok(()),
+            Ok(()) => {
+                self.update_manifest();
+                Ok(())
+            },
             Err(e) => {
                 log_plain!(Error, LOGSRC, &format!("JobRunner failed to start raw job {}: {}", &self.job_dir.to_str().unwrap_or(logging::INV_FILE_PATH), e));
                 Err(JobError::InternalError)
@@ -683,8 +1160,66 @@ impl Job {
         }
     }
 
+    /* Runs new commands on top of the output of the stages that precede the first stage of the commands.
+     * Trajectories of the preceding stages are kept. */
+    /* Resources and timeout of the previous run apply unless new ones are requested */
+    pub fn resume_from_stage(&mut self, commands: api::Commands, pbs_resources: Option<api::PbsResources>, timeout: Option<u64>) -> Result<(), JobError> {
+        if self.commands.is_none() {
+            return Err(JobError::BadInput(String::from("Only jobs that have been run in synthetic commands mode can be resumed")));
+        }
+
+        /* Trajectory is written as the stage goes, only the last frame file marks a finished stage */
+        let stage = commands.stage;
+        let finished_stages = get_stages(&self.job_dir, mmb::LAST_FRAME_FILE_PREFIX);
+        if stage < 2 || !finished_stages.contains(&(stage - 1)) {
+            return match finished_stages.last() {
+                Some(last) => Err(JobError::BadInput(format!("Job cannot be resumed from stage {}, last finished stage is {}", stage, last))),
+                None => Err(JobError::BadInput(String::from("Job has no finished stages to resume from"))),
+            };
+        }
+
+        let pbs_resources = pbs_resources.or_else(|| self.pbs_resources.clone());
+        let timeout = timeout.or(self.timeout);
+        self.start(commands, None, pbs_resources, timeout)
+    }
+
+    pub fn resume(&mut self) -> Result<(), String> {
+        self.runner.resume()?;
+        if let Some(suspended) = self.suspended_on.take() {
+            self.suspended_for += SystemTime::now().duration_since(suspended).unwrap_or_default();
+        }
+        self.update_manifest();
+        Ok(())
+    }
+
+    /* Stops the job at the request of the user. Job that is no longer active is left
+     * alone so that the outcome of its run is kept. */
     pub fn stop(&mut self) -> Result<(), String> {
-        self.runner.stop()
+        if !self.executor_state()?.is_active() {
+            return Ok(());
+        }
+        self.runner.stop()?;
+
+        self.cancelled = true;
+        self.update_manifest();
+
+        Ok(())
+    }
+
+    pub fn stop_over_quota(&mut self) -> Result<(), String> {
+        self.runner.stop()?;
+
+        self.quota_exceeded = true;
+        self.update_manifest();
+
+        Ok(())
+    }
+
+    pub fn suspend(&mut self) -> Result<(), String> {
+        self.runner.suspend()?;
+        self.suspended_on = Some(SystemTime::now());
+        self.update_manifest();
+        Ok(())
     }
 
     pub fn terminate_hung_uploads(&mut self) -> Result<(), ()> {
@@ -711,7 +1246,23 @@ impl Job {
         Ok(())
     }
 
+    pub fn uploaded_since_refresh(&self) -> u64 {
+        self.uploaded_since_refresh
+    }
+
     pub fn upload_chunk(&mut self, transfer_id: &Uuid, index: u32, chunk: Vec<u8>) -> Result<(), String> {
+        if !self.file_transfers.contains_key(transfer_id) {
+            return Err(String::from("No such transfer"));
+        }
+
+        if let Some(quota) = config::get().job_quota {
+            let usage = self.disk_usage();
+            if usage + chunk.len() as u64 > quota {
+                self.cancel_upload(transfer_id)?;
+                return Err(format!("Upload was cancelled because it would exceed the disk quota of the job ({} of {} bytes used)", usage, quota));
+            }
+        }
+
         match self.file_transfers.get_mut(transfer_id) {
             Some(xfr) => {
                 let expected_index = xfr.last_index.wrapping_add(1);
@@ -719,10 +1270,11 @@ impl Job {
                     return Err(String::from("Invalid chunk index"));
                 }
 
-                match xfr.fh.write(&chunk) {
+                match xfr.fh.write_all(&chunk) {
                     Ok(_) => {
                         xfr.last_index = expected_index;
                         xfr.last_activity = SystemTime::now();
+                        self.uploaded_since_refresh += chunk.len() as u64;
                         Ok(())
                     },
                     Err(e) => Err(format!("Failed to write file: {}", e.to_string())),
@@ -741,6 +1293,55 @@ impl Job {
         }
     }
 
+    /* Job the user has stopped is reported as cancelled and job stopped for exceeding its timeout
+     * or disk quota as failed once it is over, even if the executor cannot tell why it ended, for
+     * example after the server has been restarted */
+    fn final_state(&self, state: mmb::State) -> mmb::State {
+        match state {
+            mmb::State::Failed | mmb::State::NotStarted | mmb::State::Unknown | mmb::State::Cancelled if self.timed_out || self.quota_exceeded => mmb::State::Failed,
+            mmb::State::Failed | mmb::State::NotStarted | mmb::State::Unknown if self.cancelled => mmb::State::Cancelled,
+            _ => state,
+        }
+    }
+
+    fn executor_state(&mut self) -> Result<mmb::State, String> {
+        let state = self.runner.executor_state()?;
+
+        /* Local jobs leave the queue on their own. Record the spawned process so that
+         * it can be adopted after restart. */
+        let mut changed = self.last_executor_state == mmb::State::Queued && state != mmb::State::Queued;
+        /* Time spent waiting in a queue does not count towards the timeout */
+        if state == mmb::State::Running && self.run_started_on.is_none() {
+            self.run_started_on = Some(SystemTime::now());
+            changed = true;
+        }
+        if changed {
+            self.update_manifest();
+        }
+        self.last_executor_state = state;
+
+        Ok(state)
+    }
+
+    fn failure_reason(&mut self, state: mmb::State) -> Option<FailureReason> {
+        match state {
+            mmb::State::Failed if self.timed_out => Some(FailureReason::TimedOut),
+            mmb::State::Failed if self.quota_exceeded => Some(FailureReason::DiskQuotaExceeded),
+            mmb::State::Failed => match self.runner.failure_reason() {
+                Some(FailureReason::WalltimeLimitExceeded) if self.walltime_from_timeout => Some(FailureReason::TimedOut),
+                reason => reason,
+            },
+            _ => None,
+        }
+    }
+
+    fn run_summary(&mut self, state: mmb::State) -> Option<RunSummary> {
+        match state {
+            mmb::State::Finished | mmb::State::Failed | mmb::State::Cancelled => self.runner.run_summary(),
+            _ => None,
+        }
+    }
+
     fn prune_job_dir(&self, first_stage: i32) -> Result<(), ()> {
         let mut failed = false;
 
@@ -774,6 +1375,56 @@ impl Job {
         }
     }
 
+    fn save_manifest(&self) -> Result<(), String> {
+        let job_manifest = manifest::JobManifest{
+            name: self.name.clone(),
+            commands: self.commands.clone(),
+            raw_commands: self.raw_commands.clone(),
+            created_on: self.created_on,
+            additional_files: self.additional_files.iter().map(|(k, v)| manifest::AdditionalFileManifest{ name: k.clone(), size: v.size }).collect(),
+            runner: self.runner.manifest(),
+            runner_profile: Some(self.runner_profile.clone()),
+            cancelled: self.cancelled,
+            timeout: self.timeout,
+            run_started_on: self.run_started_on,
+            suspended_for: self.suspended_for,
+            suspended_on: self.suspended_on,
+            timed_out: self.timed_out,
+            quota_exceeded: self.quota_exceeded,
+            walltime_from_timeout: self.walltime_from_timeout,
+            pbs_resources: self.pbs_resources.clone(),
+        };
+
+        manifest::write(&manifest::mk_job_manifest_path(self.job_dir.clone()), &job_manifest)
+    }
+
+    /* Creates a fresh runner for the next run of the job. Profile of the job is kept unless a different one is requested.
+     * Also tells whether the timeout has become the PBS walltime of the run. */
+    fn prepare_runner(&self, runner_profile: Option<String>, pbs_resources: Option<api::PbsResources>, timeout: Option<u64>) -> Result<(String, Box<dyn JobRunner + Send + Sync>, bool), JobError> {
+        let runner_profile = runner_profile.unwrap_or_else(|| self.runner_profile.clone());
+        let mut profile = resolve_runner_profile(&runner_profile)?;
+        let mut pbs_resources = resolve_pbs_resources(&mut profile, pbs_resources)?;
+
+        /* PBS and Slurm stop the job on their own once the time limit is up, even if this server is not running */
+        let mut walltime_from_timeout = false;
+        if let Some(secs) = timeout {
+            walltime_from_timeout = match profile.backend {
+                RunnerBackend::Local => false,
+                RunnerBackend::Pbs => pbs_resources.walltime.is_none_or(|walltime| secs <= walltime),
+                RunnerBackend::Slurm => true,
+            };
+            pbs_resources.walltime = Some(pbs_resources.walltime.map_or(secs, |walltime| walltime.min(secs)));
+        }
+
+        match mk_runner(&profile, pbs_resources, timeout) {
+            Ok(runner) => Ok((runner_profile, runner, walltime_from_timeout)),
+            Err(e) => {
+                log_plain!(Error, LOGSRC, &format!("Failed to create runner for job {}: {}", self.job_dir.to_str().unwrap_or(logging::INV_FILE_PATH), e));
+                Err(JobError::InternalError)
+            },
+        }
+    }
+
     fn terminate_transfer(&mut self, id: &Uuid) {
         let file_name = self.file_transfers.remove(id).unwrap().file_name;
 
@@ -785,18 +1436,10 @@ impl Job {
 
         log_plain!(Info, LOGSRC, "Terminating hung file transfer");
     }
-}
 
-impl Drop for Job {
-    fn drop(&mut self) {
-        let xfr_ids: Vec<Uuid> = self.file_transfers.keys().map(|&id| id.clone()).collect();
-        for id in xfr_ids {
-            self.terminate_transfer(&id);
-        }
-
-        if let Err(e) = std::fs::remove_dir_all(&self.job_dir) {
-            log_plain!(Error, LOGSRC, &format!("Cannot delete job directory {}: {}", &self.job_dir.to_str().unwrap_or(logging::INV_FILE_PATH), e.to_string()));
+    fn update_manifest(&self) {
+        if let Err(e) = self.save_manifest() {
+            log_plain!(Error, LOGSRC, &format!("Failed to update manifest of job {}: {}", self.job_dir.to_str().unwrap_or(logging::INV_FILE_PATH), e));
         }
-        println!("Job dropped");
     }
 }
@@ -1,9 +1,89 @@
 use crate::mmb;
+use serde_derive::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
+use super::local_job_runner::ProcessIdentity;
+
+pub const STDERR_FILE_NAME: &str = "job_stderr.txt";
+pub const STDOUT_FILE_NAME: &str = "job_stdout.txt";
+
+/* Identifies the runner that has been used to run a job. This is stored in the job manifest
+ * so that the runner can be recreated and re-attached to the running job when the server restarts. */
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "kind")]
+pub enum RunnerManifest {
+    Local {
+        #[serde(default)]
+        process: Option<ProcessIdentity>,
+        /* Job was waiting for a free slot in the local scheduler */
+        #[serde(default)]
+        queued: bool,
+    },
+    Pbs {
+        job_no: Option<u32>,
+        #[serde(default)]
+        queue: Option<String>,
+    },
+    Slurm {
+        job_id: Option<u32>,
+        #[serde(default)]
+        partition: Option<String>,
+        /* Pending job has been held by SuspendJob */
+        #[serde(default)]
+        held: bool,
+    },
+}
+
+/* Reason why the executor terminated a job */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FailureReason {
+    CpuTimeLimitExceeded,
+    MemoryLimitExceeded,
+    WalltimeLimitExceeded,
+    NonZeroExitStatus,
+    KilledBySignal,
+    /* Job management system failed to execute the job */
+    ExecutionFailed,
+    /* Job ran for longer than its timeout and has been stopped */
+    TimedOut,
+    /* Job or its session occupied more disk space than allowed and the job has been stopped */
+    DiskQuotaExceeded,
+}
+
+/* Details of a finished run as reported by the executor */
+#[derive(Clone)]
+pub struct RunSummary {
+    pub exit_status: Option<i32>,
+    /* Signal that terminated the job, if any */
+    pub signal: Option<i32>,
+    pub exec_host: Option<String>,
+    pub cpu_time: Option<String>,
+    pub walltime: Option<String>,
+}
+
+/* Formats a duration in seconds as HH:MM:SS */
+pub fn format_hms(secs: u64) -> String {
+    format!("{:02}:{:02}:{:02}", secs / 3600, (secs % 3600) / 60, secs % 60)
+}
+
+pub fn mk_stderr_file_path(mut base_path: PathBuf) -> PathBuf {
+    base_path.push(STDERR_FILE_NAME);
+    base_path
+}
+
+pub fn mk_stdout_file_path(mut base_path: PathBuf) -> PathBuf {
+    base_path.push(STDOUT_FILE_NAME);
+    base_path
+}
+
 pub trait JobRunner {
     fn executor_state(&mut self) -> Result<mmb::State, String>;
+    fn failure_reason(&mut self) -> Option<FailureReason>;
+    fn manifest(&self) -> RunnerManifest;
     fn prune_job_dir(&self, job_dir: PathBuf) -> Result<(), String>;
+    fn resume(&mut self) -> Result<(), String>;
+    fn run_summary(&mut self) -> Option<RunSummary>;
     fn start(&mut self, job_dir: PathBuf, cmds_file_path: &Path, diag_file_path: &Path, progress_file_path: &Path) -> Result<(), String>;
     fn stop(&mut self) -> Result<(), String>;
+    fn suspend(&mut self) -> Result<(), String>;
 }
@@ -1,118 +1,601 @@
+use nix::libc;
 use nix::unistd::Pid;
 use nix::sys::signal::{self, Signal};
+use serde_derive::{Deserialize, Serialize};
+use std::fs::File;
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::process::{Child, Command};
-use std::thread;
-use std::time::Duration;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::config;
+use crate::logging;
+use crate::log_plain;
 use crate::mmb;
 use super::job_runner;
+use super::local_scheduler;
 
-fn check_process(proc: &mut Option<Child>) -> Result<mmb::State, String> {
-    if proc.is_none() {
-        return Ok(mmb::State::Unknown);
+const LOGSRC: &str = "local_job_runner";
+/* Time a job is given to terminate after it has been asked to stop */
+const STOP_GRACE_PERIOD: Duration = Duration::from_secs(10);
+/* RLIMIT_DATA of a job is this many times its resident memory limit */
+const DATA_LIMIT_FACTOR: u64 = 2;
+
+/* PID alone cannot identify a process because PIDs get reused.
+ * Start time of the process (in clock ticks since boot) is recorded along with the PID. */
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ProcessIdentity {
+    pub pid: i32,
+    pub start_time: u64,
+}
+
+/* Exit status and resource usage of a process that has terminated */
+#[derive(Clone, Copy)]
+struct ProcessExit {
+    /* Raw status as returned by wait */
+    status: libc::c_int,
+    cpu_time: Duration,
+    walltime: Duration,
+}
+
+pub struct SpawnedProcess {
+    pid: i32,
+    identity: Option<ProcessIdentity>,
+    /* Set when the process has been killed for exceeding a limit */
+    breached: Option<job_runner::FailureReason>,
+    started: Instant,
+    exit: Option<ProcessExit>,
+    /* Set when the process has been asked to stop. It is killed if it is still running by then. */
+    kill_at: Option<Instant>,
+    suspended: bool,
+}
+
+pub enum MmbProcess {
+    /* Waiting in the local scheduler for a free slot */
+    Queued,
+    /* Withdrawn from the queue before it could run */
+    Cancelled,
+    SpawnFailed,
+    Spawned(SpawnedProcess),
+    /* Process started by a previous instance of the server. It is not our child
+     * so we cannot wait for it, we can only check whether it is still around. */
+    Adopted {
+        identity: ProcessIdentity,
+        kill_at: Option<Instant>,
+        suspended: bool,
+    },
+}
+
+/* Process slot shared between the runner and the local scheduler which fills it in once the job is let to run */
+pub type SharedProcess = Arc<Mutex<Option<MmbProcess>>>;
+
+fn check_process(proc: &mut Option<MmbProcess>) -> Result<mmb::State, String> {
+    match proc.as_mut() {
+        Some(MmbProcess::Queued) => Ok(mmb::State::Queued),
+        Some(MmbProcess::Cancelled) => Ok(mmb::State::Cancelled),
+        Some(MmbProcess::SpawnFailed) => Ok(mmb::State::Failed),
+        Some(MmbProcess::Spawned(spawned)) => match spawned.reap()? {
+            Some(exit) => {
+                if spawned.kill_at.is_some() {
+                    return Ok(mmb::State::Cancelled);
+                }
+                if libc::WIFEXITED(exit.status) && libc::WEXITSTATUS(exit.status) == 0 {
+                    return Ok(mmb::State::Finished);
+                }
+                Ok(mmb::State::Failed)
+            },
+            None => match (spawned.kill_at, spawned.suspended) {
+                (Some(_), _) => Ok(mmb::State::Stopping),
+                (None, true) => Ok(mmb::State::Suspended),
+                (None, false) => Ok(mmb::State::Running),
+            },
+        },
+        Some(MmbProcess::Adopted{ identity, kill_at, suspended }) => {
+            /* Exit status of an adopted process is not available.
+             * Let the caller decide from the progress reported by MMB */
+            match (is_alive(identity), kill_at, suspended) {
+                (true, Some(_), _) => Ok(mmb::State::Stopping),
+                (true, None, true) => Ok(mmb::State::Suspended),
+                (true, None, false) => Ok(mmb::State::Running),
+                (false, Some(_), _) => Ok(mmb::State::Cancelled),
+                (false, None, _) => Ok(mmb::State::Unknown),
+            }
+        },
+        None => Ok(mmb::State::Unknown),
     }
+}
 
-    match proc.as_mut().unwrap().try_wait() {
-        Ok(exit) => {
-            match exit {
-                Some(status) => {
-                    if status.success() {
-                        return Ok(mmb::State::Finished);
-                    }
-                    return Ok(mmb::State::Failed);
-                },
-                None => Ok(mmb::State::Running)
+/* CPU time limit is enforced by the kernel through RLIMIT_CPU. Resident memory cannot be limited
+ * by an rlimit so it is checked periodically and the process is killed once it exceeds the limit.
+ * RLIMIT_DATA set at spawn only stops a process that grows too fast for the periodic check. */
+pub fn enforce_limits(proc: &mut Option<MmbProcess>) {
+    let limit = match config::get().local_job_limits.memory {
+        Some(v) => v,
+        None => return,
+    };
+
+    if let Some(MmbProcess::Spawned(spawned)) = proc.as_mut() {
+        if spawned.breached.is_some() {
+            return;
+        }
+        if !matches!(spawned.reap(), Ok(None)) {
+            return;
+        }
+
+        let rss = match read_rss(spawned.pid) {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        if rss > limit {
+            log_plain!(Warning, LOGSRC, &format!("Killing MMB process {} because it exceeded the memory limit ({} of {} bytes)", spawned.pid, rss, limit));
+            if signal_group(spawned.pid, Signal::SIGKILL).is_ok() {
+                spawned.breached = Some(job_runner::FailureReason::MemoryLimitExceeded);
+            }
+        }
+    }
+}
+
+/* Kills the process group of a job that was asked to stop and did not terminate within the grace period.
+ * Helper processes left behind by an MMB process that has already exited are killed right away. */
+pub fn escalate_stop(proc: &mut Option<MmbProcess>) {
+    match proc.as_mut() {
+        Some(MmbProcess::Spawned(spawned)) => {
+            let kill_at = match spawned.kill_at {
+                Some(v) => v,
+                None => return,
+            };
+
+            if !matches!(spawned.reap(), Ok(None)) {
+                /* The PID may have been reused already, only the process group is signalled */
+                let _ = signal::killpg(Pid::from_raw(spawned.pid), Signal::SIGKILL);
+            } else if Instant::now() >= kill_at {
+                log_plain!(Warning, LOGSRC, &format!("MMB process {} did not terminate in time, killing it", spawned.pid));
+                let _ = signal_group(spawned.pid, Signal::SIGKILL);
             }
         },
+        Some(MmbProcess::Adopted{ identity, kill_at: Some(kill_at), .. }) if is_alive(identity) && Instant::now() >= *kill_at => {
+            log_plain!(Warning, LOGSRC, &format!("MMB process {} did not terminate in time, killing it", identity.pid));
+            let _ = signal_group(identity.pid, Signal::SIGKILL);
+        },
+        _ => {},
+    }
+}
+
+/* Suspended process keeps its slot in the local scheduler but does not count towards the limit of running jobs */
+pub fn is_suspended(proc: &Option<MmbProcess>) -> bool {
+    match proc {
+        Some(MmbProcess::Spawned(spawned)) => spawned.suspended,
+        Some(MmbProcess::Adopted{ suspended, .. }) => *suspended,
+        _ => false,
+    }
+}
+
+/* Tells whether the process still occupies a slot in the local scheduler */
+pub fn is_active(proc: &mut Option<MmbProcess>) -> bool {
+    match proc.as_mut() {
+        Some(MmbProcess::Spawned(spawned)) => matches!(spawned.reap(), Ok(None)),
+        Some(MmbProcess::Adopted{ identity, .. }) => is_alive(identity),
+        _ => false,
+    }
+}
+
+fn is_alive(identity: &ProcessIdentity) -> bool {
+    match read_proc_stat(identity.pid) {
+        Ok((state, start_time)) => state != 'Z' && start_time == identity.start_time,
+        Err(_) => false,
+    }
+}
+
+fn process_identity(pid: i32) -> Result<ProcessIdentity, String> {
+    match read_proc_stat(pid) {
+        Ok((_, start_time)) => Ok(ProcessIdentity{ pid, start_time }),
+        Err(e) => Err(e),
+    }
+}
+
+/* Returns the state and the start time of a process as reported by /proc/<pid>/stat */
+fn read_proc_stat(pid: i32) -> Result<(char, u64), String> {
+    let stat = match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(v) => v,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    /* Process name is enclosed in parentheses and may contain spaces.
+     * Fields that follow start with the process state which is the third field overall */
+    let tail = match stat.rfind(')') {
+        Some(idx) => &stat[idx + 1..],
+        None => return Err(String::from("Malformed process stat")),
+    };
+    let fields = tail.split_whitespace().collect::<Vec<&str>>();
+    if fields.len() < 20 {
+        return Err(String::from("Malformed process stat"));
+    }
+
+    let state = match fields[0].chars().next() {
+        Some(c) => c,
+        None => return Err(String::from("Malformed process stat")),
+    };
+    let start_time = match fields[19].parse::<u64>() {
+        Ok(v) => v,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    Ok((state, start_time))
+}
+
+/* Returns the resident set size of a process in bytes as reported by /proc/<pid>/status */
+fn read_rss(pid: i32) -> Result<u64, String> {
+    let status = match std::fs::read_to_string(format!("/proc/{}/status", pid)) {
+        Ok(v) => v,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    for line in status.lines() {
+        if let Some(value) = line.strip_prefix("VmRSS:") {
+            let kbytes = value.trim().trim_end_matches("kB").trim();
+            return match kbytes.parse::<u64>() {
+                Ok(v) => Ok(v * 1024),
+                Err(e) => Err(e.to_string()),
+            };
+        }
+    }
+
+    Err(String::from("Process status does not report resident memory"))
+}
+
+/* MMB runs in its own process group so that helper processes it starts are signalled along with it.
+ * Processes started by older versions of the server may not lead a process group, only the process is signalled then. */
+fn signal_group(pid: i32, sig: Signal) -> Result<(), String> {
+    match signal::killpg(Pid::from_raw(pid), sig) {
+        Ok(()) => Ok(()),
+        Err(_) => match signal::kill(Pid::from_raw(pid), sig) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        },
+    }
+}
+
+fn timeval_to_duration(tv: libc::timeval) -> Duration {
+    Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+}
+
+/* Jobs of a session live in the directory of the session */
+fn scheduling_group(job_dir: &Path) -> String {
+    match job_dir.parent().and_then(|p| p.file_name()).and_then(|name| name.to_str()) {
+        Some(name) => String::from(name),
+        None => String::new(),
+    }
+}
+
+fn spawn_mmb(job_dir: PathBuf, cmds_file_path: PathBuf, diag_file_path: PathBuf, progress_file_path: PathBuf) -> Result<MmbProcess, String> {
+    let limits = config::get().local_job_limits.clone();
+
+    let stdout = match File::create(job_runner::mk_stdout_file_path(job_dir.clone())) {
+        Ok(fh) => fh,
+        Err(e) => return Err(format!("Cannot create stdout output file: {}", e)),
+    };
+    let stderr = match File::create(job_runner::mk_stderr_file_path(job_dir.clone())) {
+        Ok(fh) => fh,
+        Err(e) => return Err(format!("Cannot create stderr output file: {}", e)),
+    };
+
+    let mut cmd = Command::new(&config::get().mmb_exec_path);
+    cmd.current_dir(&job_dir)
+        .arg("-C")
+        .arg(cmds_file_path)
+        .arg("-progress")
+        .arg(progress_file_path)
+        .arg("-output")
+        .arg(diag_file_path)
+        .stdout(Stdio::from(stdout))
+        .stderr(Stdio::from(stderr));
+
+    if let Some(threads) = limits.threads {
+        cmd.env("OMP_NUM_THREADS", threads.to_string());
+    }
+
+    let cpu_time = limits.cpu_time;
+    let data_size = limits.memory.map(|bytes| bytes.saturating_mul(DATA_LIMIT_FACTOR));
+    let nice = limits.nice;
+    /* Runs in the forked child before exec. Only async-signal-safe calls are allowed here. */
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setpgid(0, 0) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if let Some(secs) = cpu_time {
+                /* Soft limit delivers SIGXCPU, hard limit a bit later kills the process if it ignores the signal */
+                let rlim = libc::rlimit{ rlim_cur: secs as libc::rlim_t, rlim_max: (secs + 5) as libc::rlim_t };
+                if libc::setrlimit(libc::RLIMIT_CPU, &rlim) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            if let Some(bytes) = data_size {
+                let rlim = libc::rlimit{ rlim_cur: bytes as libc::rlim_t, rlim_max: bytes as libc::rlim_t };
+                if libc::setrlimit(libc::RLIMIT_DATA, &rlim) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            if let Some(nice) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+
+    /* Process is waited for by its PID in SpawnedProcess::reap so that its resource usage can be collected */
+    match cmd.spawn() {
+        Ok(child) => {
+            let pid = child.id() as i32;
+            let identity = match process_identity(pid) {
+                Ok(identity) => Some(identity),
+                Err(e) => {
+                    log_plain!(Warning, LOGSRC, &format!("Cannot identify MMB process {}, it will not be possible to adopt it after restart: {}", pid, e));
+                    None
+                },
+            };
+            Ok(MmbProcess::Spawned(SpawnedProcess{
+                pid,
+                identity,
+                breached: None,
+                started: Instant::now(),
+                exit: None,
+                kill_at: None,
+                suspended: false,
+            }))
+        },
         Err(e) => Err(e.to_string()),
     }
 }
 
+impl SpawnedProcess {
+    /* Collects the exit status of the process once it has terminated */
+    fn reap(&mut self) -> Result<Option<ProcessExit>, String> {
+        if self.exit.is_some() {
+            return Ok(self.exit);
+        }
+
+        let mut status: libc::c_int = 0;
+        let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+        match unsafe { libc::wait4(self.pid, &mut status, libc::WNOHANG, &mut rusage) } {
+            0 => Ok(None),
+            -1 => Err(std::io::Error::last_os_error().to_string()),
+            _ => {
+                self.exit = Some(ProcessExit{
+                    status,
+                    cpu_time: timeval_to_duration(rusage.ru_utime) + timeval_to_duration(rusage.ru_stime),
+                    walltime: self.started.elapsed(),
+                });
+                Ok(self.exit)
+            },
+        }
+    }
+}
+
 pub struct LocalJobRunner {
-    mmb_process: Option<Child>,
+    mmb_process: SharedProcess,
 }
 
 impl job_runner::JobRunner for LocalJobRunner {
     fn executor_state(&mut self) -> Result<mmb::State, String> {
-        check_process(&mut self.mmb_process)
+        check_process(&mut self.mmb_process.lock().unwrap())
     }
 
-    fn prune_job_dir(&self, _job_dir: PathBuf) -> Result<(), String> {
-        /* Nothing specific to do for this Runner */
+    fn failure_reason(&mut self) -> Option<job_runner::FailureReason> {
+        let spawned = match self.mmb_process.lock().unwrap().as_mut() {
+            Some(MmbProcess::Spawned(spawned)) => {
+                if spawned.breached.is_some() {
+                    return spawned.breached;
+                }
+                spawned.reap()
+            },
+            _ => return None,
+        };
+
+        match spawned {
+            Ok(Some(exit)) => {
+                if libc::WIFSIGNALED(exit.status) {
+                    match libc::WTERMSIG(exit.status) {
+                        libc::SIGXCPU => Some(job_runner::FailureReason::CpuTimeLimitExceeded),
+                        _ => Some(job_runner::FailureReason::KilledBySignal),
+                    }
+                } else if libc::WEXITSTATUS(exit.status) != 0 {
+                    Some(job_runner::FailureReason::NonZeroExitStatus)
+                } else {
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn manifest(&self) -> job_runner::RunnerManifest {
+        let (process, queued) = match &*self.mmb_process.lock().unwrap() {
+            Some(MmbProcess::Queued) => (None, true),
+            Some(MmbProcess::Spawned(spawned)) => (spawned.identity.clone(), false),
+            Some(MmbProcess::Adopted{ identity, .. }) => (Some(identity.clone()), false),
+            _ => (None, false),
+        };
+
+        job_runner::RunnerManifest::Local { process, queued }
+    }
+
+    fn prune_job_dir(&self, job_dir: PathBuf) -> Result<(), String> {
+        for file_path in [job_runner::mk_stdout_file_path(job_dir.clone()), job_runner::mk_stderr_file_path(job_dir)].iter() {
+            if !file_path.exists() {
+                continue;
+            }
+            if let Err(e) = std::fs::remove_file(file_path) {
+                log_plain!(Error, LOGSRC, &format!("Cannot delete output file {}: {}", file_path.to_str().unwrap_or(logging::INV_FILE_PATH), e));
+            }
+        }
+
         Ok(())
     }
 
-    fn start(&mut self, job_dir: PathBuf, cmds_file_path: &Path, diag_file_path: &Path, progress_file_path: &Path) -> Result<(), String> {
-        match Command::new(&config::get().mmb_exec_path)
-            .current_dir(&job_dir)
-            .arg("-C")
-            .arg(cmds_file_path)
-            .arg("-progress")
-            .arg(progress_file_path)
-            .arg("-output")
-            .arg(diag_file_path)
-            .spawn() {
-            Ok(child) => self.mmb_process = Some(child),
-            Err(e) => return Err(e.to_string()),
+    fn resume(&mut self) -> Result<(), String> {
+        let mmb_process = self.mmb_process.clone();
+        local_scheduler::readmit(&mmb_process, || self.signal_running(Signal::SIGCONT, false))
+    }
+
+    fn run_summary(&mut self) -> Option<job_runner::RunSummary> {
+        let exit = match self.mmb_process.lock().unwrap().as_mut() {
+            Some(MmbProcess::Spawned(spawned)) => match spawned.reap() {
+                Ok(Some(exit)) => exit,
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        let (exit_status, signal) = if libc::WIFSIGNALED(exit.status) {
+            (None, Some(libc::WTERMSIG(exit.status)))
+        } else {
+            (Some(libc::WEXITSTATUS(exit.status)), None)
         };
 
+        Some(job_runner::RunSummary{
+            exit_status,
+            signal,
+            exec_host: None,
+            cpu_time: Some(job_runner::format_hms(exit.cpu_time.as_secs())),
+            walltime: Some(job_runner::format_hms(exit.walltime.as_secs())),
+        })
+    }
+
+    fn start(&mut self, job_dir: PathBuf, cmds_file_path: &Path, diag_file_path: &Path, progress_file_path: &Path) -> Result<(), String> {
+        let group = scheduling_group(&job_dir);
+        let cmds_file_path = cmds_file_path.to_path_buf();
+        let diag_file_path = diag_file_path.to_path_buf();
+        let progress_file_path = progress_file_path.to_path_buf();
+
+        local_scheduler::enqueue(
+            group,
+            self.mmb_process.clone(),
+            Box::new(move || spawn_mmb(job_dir, cmds_file_path, diag_file_path, progress_file_path))
+        );
+
         Ok(())
     }
 
     fn stop(&mut self) -> Result<(), String> {
-        if self.mmb_process.is_none() {
+        /* Job that has not been let to run yet only needs to leave the queue */
+        if local_scheduler::withdraw(&self.mmb_process) {
+            *self.mmb_process.lock().unwrap() = Some(MmbProcess::Cancelled);
             return Ok(());
         }
 
-        let pid = self.mmb_process.as_ref().unwrap().id();
-        if signal::kill(Pid::from_raw(pid as i32), Signal::SIGTERM).is_err() {
-            return Err(String::from("Failed to signal job process"));
+        /* Termination is only requested here. The local scheduler kills the process group
+         * if the job does not terminate within the grace period. */
+        let mut process = self.mmb_process.lock().unwrap();
+        let (pid, kill_at) = match process.as_mut() {
+            Some(MmbProcess::Spawned(spawned)) => {
+                if !matches!(spawned.reap(), Ok(None)) {
+                    return Ok(());
+                }
+                (spawned.pid, &mut spawned.kill_at)
+            },
+            Some(MmbProcess::Adopted{ identity, kill_at, .. }) => {
+                if !is_alive(identity) {
+                    return Ok(());
+                }
+                (identity.pid, kill_at)
+            },
+            _ => return Ok(()),
+        };
+
+        if kill_at.is_some() {
+            /* Job is already stopping */
+            return Ok(());
         }
 
-        let terminated = || -> bool {
-            let mut attempts = 0;
-            while attempts < 10 {
-                match self.mmb_process.as_mut().unwrap().try_wait() {
-                    Ok(ret) => match ret {
-                        Some(_) => return true,
-                        None => {
-                            attempts += 1;
-                        },
-                    },
-                    Err(_) => {
-                        return false;
-                    }
-                };
-                thread::sleep(Duration::from_millis(1000));
-            }
-            false
-        }();
+        if signal_group(pid, Signal::SIGTERM).is_err() {
+            return Err(String::from("Failed to signal job process"));
+        }
+        *kill_at = Some(Instant::now() + STOP_GRACE_PERIOD);
 
-        if !terminated {
-            if self.mmb_process.as_mut().unwrap().kill().is_err() {
-                return Err(String::from("Failed to kill job process"));
+        /* Stopped process would not act on SIGTERM until it is continued */
+        if let Some(MmbProcess::Spawned(SpawnedProcess{ suspended, .. })) | Some(MmbProcess::Adopted{ suspended, .. }) = process.as_mut() {
+            if *suspended {
+                let _ = signal_group(pid, Signal::SIGCONT);
+                *suspended = false;
             }
         }
 
         Ok(())
     }
+
+    fn suspend(&mut self) -> Result<(), String> {
+        self.signal_running(Signal::SIGSTOP, true)
+    }
 }
 
 impl LocalJobRunner {
     pub fn create() -> Result<LocalJobRunner, String> {
         Ok(LocalJobRunner{
-            mmb_process: None,
+            mmb_process: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    pub fn restore(process: Option<ProcessIdentity>, job_dir: &Path) -> Result<LocalJobRunner, String> {
+        let mmb_process = Arc::new(Mutex::new(None));
+
+        if let Some(identity) = process {
+            if is_alive(&identity) {
+                log_plain!(Info, LOGSRC, &format!("Adopting running MMB process {}", identity.pid));
+                /* Process that has been suspended before the restart is still stopped */
+                let suspended = matches!(read_proc_stat(identity.pid), Ok(('T', _)));
+                *mmb_process.lock().unwrap() = Some(MmbProcess::Adopted{ identity, kill_at: None, suspended });
+                /* Adopted process counts towards the limit of concurrently running jobs */
+                local_scheduler::admit(scheduling_group(job_dir), mmb_process.clone());
+            }
+        }
+
+        Ok(LocalJobRunner{
+            mmb_process,
         })
     }
+
+    /* Stops or continues the whole process group of a running job */
+    fn signal_running(&mut self, sig: Signal, suspend: bool) -> Result<(), String> {
+        let mut process = self.mmb_process.lock().unwrap();
+        let (pid, kill_at, suspended) = match process.as_mut() {
+            Some(MmbProcess::Spawned(spawned)) => {
+                if !matches!(spawned.reap(), Ok(None)) {
+                    return Err(String::from("Job is not running"));
+                }
+                (spawned.pid, spawned.kill_at, &mut spawned.suspended)
+            },
+            Some(MmbProcess::Adopted{ identity, kill_at, suspended }) => {
+                if !is_alive(identity) {
+                    return Err(String::from("Job is not running"));
+                }
+                (identity.pid, *kill_at, suspended)
+            },
+            _ => return Err(String::from("Job is not running")),
+        };
+
+        if kill_at.is_some() {
+            return Err(String::from("Job is being stopped"));
+        }
+        if *suspended == suspend {
+            return Ok(());
+        }
+
+        if signal_group(pid, sig).is_err() {
+            return Err(String::from("Failed to signal job process"));
+        }
+        *suspended = suspend;
+
+        Ok(())
+    }
 }
 
 impl Drop for LocalJobRunner {
     fn drop(&mut self) {
-        match self.mmb_process.as_mut() {
-            Some(p) => assert!(p.try_wait().is_ok()),
-            None => {},
-        };
+        /* Process that is still running is reaped by the local scheduler once it exits */
+        local_scheduler::withdraw(&self.mmb_process);
     }
 }
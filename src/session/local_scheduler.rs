@@ -0,0 +1,167 @@
+use lazy_static::lazy_static;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{self, LocalSchedulingPolicy};
+use crate::logging;
+use crate::log_plain;
+use super::local_job_runner::{self, MmbProcess, SharedProcess};
+
+const LOGSRC: &str = "local_scheduler";
+const DISPATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub type SpawnFn = Box<dyn FnOnce() -> Result<MmbProcess, String> + Send>;
+
+/* Job waiting for a free slot. Jobs are grouped by the session they belong to
+ * so that fair-share scheduling can tell the sessions apart. */
+struct Launch {
+    group: String,
+    process: SharedProcess,
+    spawn: SpawnFn,
+}
+
+/* Job that occupies a slot */
+struct Admitted {
+    group: String,
+    process: SharedProcess,
+}
+
+struct LocalScheduler {
+    waiting: VecDeque<Launch>,
+    admitted: Vec<Admitted>,
+}
+
+lazy_static! {
+    static ref SCHEDULER: Mutex<LocalScheduler> = Mutex::new(
+        LocalScheduler{
+            waiting: VecDeque::new(),
+            admitted: Vec::new(),
+        }
+    );
+}
+
+impl LocalScheduler {
+    /* Suspended jobs do not count as running */
+    fn running(&self, group: Option<&str>) -> usize {
+        self.admitted.iter()
+            .filter(|adm| group.is_none_or(|g| adm.group == g))
+            .filter(|adm| !local_job_runner::is_suspended(&adm.process.lock().unwrap()))
+            .count()
+    }
+
+    fn dispatch(&mut self) {
+        /* Enforce limits of the running jobs and release slots of jobs that are no longer running */
+        self.admitted.retain(|adm| {
+            let mut process = adm.process.lock().unwrap();
+            local_job_runner::enforce_limits(&mut process);
+            local_job_runner::escalate_stop(&mut process);
+            local_job_runner::is_active(&mut process)
+        });
+
+        let cfg = config::get();
+        while !self.waiting.is_empty() {
+            if let Some(max) = cfg.max_local_jobs {
+                if self.running(None) >= max {
+                    break;
+                }
+            }
+
+            let idx = self.next_launch(cfg.local_scheduling_policy);
+            let launch = self.waiting.remove(idx).unwrap();
+
+            let mut process = launch.process.lock().unwrap();
+            match (launch.spawn)() {
+                Ok(proc) => {
+                    *process = Some(proc);
+                    self.admitted.push(Admitted{ group: launch.group, process: launch.process.clone() });
+                },
+                Err(e) => {
+                    log_plain!(Error, LOGSRC, &format!("Failed to spawn MMB process of a job from session {}: {}", launch.group, e));
+                    *process = Some(MmbProcess::SpawnFailed);
+                },
+            }
+        }
+    }
+
+    fn next_launch(&self, policy: LocalSchedulingPolicy) -> usize {
+        match policy {
+            LocalSchedulingPolicy::Fifo => 0,
+            LocalSchedulingPolicy::FairShare => {
+                /* Pick the longest waiting job of the session that has the fewest jobs running */
+                let mut best = 0;
+                let mut best_running = usize::MAX;
+                for (idx, launch) in self.waiting.iter().enumerate() {
+                    let running = self.running(Some(&launch.group));
+                    if running < best_running {
+                        best = idx;
+                        best_running = running;
+                    }
+                }
+                best
+            },
+        }
+    }
+}
+
+/* Registers a process that is already running, for example one adopted after restart */
+pub fn admit(group: String, process: SharedProcess) {
+    SCHEDULER.lock().unwrap().admitted.push(Admitted{ group, process });
+}
+
+/* Queues a job and starts it right away if there is a free slot */
+pub fn enqueue(group: String, process: SharedProcess, spawn: SpawnFn) {
+    let mut sched = SCHEDULER.lock().unwrap();
+
+    *process.lock().unwrap() = Some(MmbProcess::Queued);
+    sched.waiting.push_back(Launch{ group, process, spawn });
+    sched.dispatch();
+}
+
+pub fn spawn_dispatcher() -> thread::JoinHandle<()> {
+    match config::get().max_local_jobs {
+        Some(max) => log_plain!(Info, LOGSRC, &format!("At most {} local job(s) will run at the same time", max)),
+        None => log_plain!(Info, LOGSRC, "Number of concurrently running local jobs is not limited"),
+    };
+
+    thread::spawn(move || {
+        loop {
+            thread::sleep(DISPATCH_INTERVAL);
+            SCHEDULER.lock().unwrap().dispatch();
+        }
+    })
+}
+
+/* Continues a suspended job. Suspended jobs do not count as running so their slots may have been
+ * given to queued jobs in the meantime, the job is continued only if a slot is free. */
+pub fn readmit<F>(process: &SharedProcess, cont: F) -> Result<(), String>
+where
+    F: FnOnce() -> Result<(), String>,
+{
+    let sched = SCHEDULER.lock().unwrap();
+
+    if local_job_runner::is_suspended(&process.lock().unwrap()) {
+        if let Some(max) = config::get().max_local_jobs {
+            if sched.running(None) >= max {
+                return Err(format!("All {} slots for local jobs are taken, the job can be continued once one of them is free", max));
+            }
+        }
+    }
+
+    cont()
+}
+
+/* Removes a job from the queue. Returns false if the job was not waiting */
+pub fn withdraw(process: &SharedProcess) -> bool {
+    let mut sched = SCHEDULER.lock().unwrap();
+
+    match sched.waiting.iter().position(|launch| Arc::ptr_eq(&launch.process, process)) {
+        Some(idx) => {
+            sched.waiting.remove(idx);
+            *process.lock().unwrap() = None;
+            true
+        },
+        None => false,
+    }
+}
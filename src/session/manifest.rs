@@ -0,0 +1,102 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::server::api;
+use super::job_runner::RunnerManifest;
+
+pub const JOB_MANIFEST_FILE_NAME: &str = "job_manifest.json";
+pub const SESSION_MANIFEST_FILE_NAME: &str = "session_manifest.json";
+
+#[derive(Deserialize, Serialize)]
+pub struct AdditionalFileManifest {
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct JobManifest {
+    pub name: String,
+    pub commands: Option<api::Commands>,
+    pub raw_commands: Option<String>,
+    pub created_on: SystemTime,
+    pub additional_files: Vec<AdditionalFileManifest>,
+    pub runner: RunnerManifest,
+    #[serde(default)]
+    pub runner_profile: Option<String>,
+    #[serde(default)]
+    pub cancelled: bool,
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    #[serde(default)]
+    pub run_started_on: Option<SystemTime>,
+    #[serde(default)]
+    pub suspended_for: Duration,
+    #[serde(default)]
+    pub suspended_on: Option<SystemTime>,
+    #[serde(default)]
+    pub timed_out: bool,
+    #[serde(default)]
+    pub quota_exceeded: bool,
+    #[serde(default)]
+    pub walltime_from_timeout: bool,
+    #[serde(default)]
+    pub pbs_resources: Option<api::PbsResources>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SessionManifest {
+    pub created_on: SystemTime,
+    pub is_logged_in: bool,
+}
+
+pub fn mk_job_manifest_path(mut job_dir: PathBuf) -> PathBuf {
+    job_dir.push(JOB_MANIFEST_FILE_NAME);
+    job_dir
+}
+
+pub fn mk_session_manifest_path(mut session_dir: PathBuf) -> PathBuf {
+    session_dir.push(SESSION_MANIFEST_FILE_NAME);
+    session_dir
+}
+
+pub fn read<T: DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    match serde_json::from_str::<T>(data.as_str()) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(format!("Cannot parse manifest: {}", e)),
+    }
+}
+
+pub fn write<T: serde::Serialize>(path: &Path, manifest: &T) -> Result<(), String> {
+    let json = match serde_json::to_string(manifest) {
+        Ok(v) => v,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    /* Write into a temporary file first so that a crash cannot leave a truncated manifest behind */
+    let mut tmp_path = PathBuf::from(path);
+    tmp_path.set_extension("json.tmp");
+
+    let mut fh = match std::fs::File::create(&tmp_path) {
+        Ok(fh) => fh,
+        Err(e) => return Err(e.to_string()),
+    };
+    if let Err(e) = fh.write_all(json.as_bytes()) {
+        return Err(e.to_string());
+    }
+    if let Err(e) = fh.sync_all() {
+        return Err(e.to_string());
+    }
+
+    match std::fs::rename(&tmp_path, path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
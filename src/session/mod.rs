@@ -3,19 +3,53 @@ pub mod session;
 pub mod session_manager;
 
 mod local_job_runner;
+mod local_scheduler;
 mod job_runner;
+mod manifest;
 mod pbs_job_runner;
+mod slurm_job_runner;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
 use crate::mmb;
 
+pub use job_runner::{FailureReason, STDERR_FILE_NAME, STDOUT_FILE_NAME};
+pub use local_scheduler::spawn_dispatcher;
+pub use manifest::JOB_MANIFEST_FILE_NAME;
+
 pub enum JobError {
     BadInput(String),
     InternalError,
 }
 
+pub fn disk_usage(path: &Path) -> Result<u64, String> {
+    let dir_lister = match std::fs::read_dir(path) {
+        Ok(v) => v,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut total = 0;
+    for entry in dir_lister {
+        let entry = match entry {
+            Ok(v) => v,
+            Err(e) => return Err(e.to_string()),
+        };
+        let metadata = match entry.metadata() {
+            Ok(v) => v,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        if metadata.is_dir() {
+            total += disk_usage(&entry.path())?;
+        } else {
+            total += metadata.len();
+        }
+    }
+
+    Ok(total)
+}
+
 pub fn trajectory_file_path(base: &PathBuf, session_id: &str, job_id: &str, stage: i32) -> Result<PathBuf, ()> {
     let traj_file = mmb::trajectory_file_name(stage);
     let mut path = PathBuf::new();
@@ -4,7 +4,7 @@ use std::fs::File;
 use std::process::Command;
 use std::path::{Path, PathBuf};
 
-use crate::config;
+use crate::config::{self, PbsResources};
 use crate::logging;
 use crate::log_plain;
 use crate::mmb;
@@ -13,14 +13,76 @@ use super::job_runner;
 
 const LOGSRC: &'static str = "pbs_job_runner";
 
-fn mk_stderr_file_path(mut base_path: PathBuf) -> PathBuf {
-    base_path.push("job_stderr.txt");
-    base_path
+/* PBS notes the limit a job has exceeded in the stderr output of the job */
+fn read_kill_reason(stderr_file_path: &Path) -> Option<job_runner::FailureReason> {
+    let stderr = match std::fs::read_to_string(stderr_file_path) {
+        Ok(v) => v,
+        Err(_) => return None,
+    };
+
+    for line in stderr.lines() {
+        if let Some(idx) = line.find("PBS: job killed: ") {
+            let what = &line[idx + "PBS: job killed: ".len()..];
+            if what.starts_with("walltime") {
+                return Some(job_runner::FailureReason::WalltimeLimitExceeded);
+            } else if what.starts_with("cput") {
+                return Some(job_runner::FailureReason::CpuTimeLimitExceeded);
+            } else if what.starts_with("mem") || what.starts_with("vmem") {
+                return Some(job_runner::FailureReason::MemoryLimitExceeded);
+            }
+        }
+    }
+
+    None
 }
 
-fn mk_stdout_file_path(mut base_path: PathBuf) -> PathBuf {
-    base_path.push("job_stdout.txt");
-    base_path
+/* PBS reports jobs terminated by a signal with exit status 256 + signal number.
+ * Negative exit status (JOB_EXEC_FAIL1 and friends) means that PBS could not execute
+ * the job at all, there is neither an exit status of MMB nor a signal. */
+fn split_exit_status(exit_status: i32) -> (Option<i32>, Option<i32>) {
+    if exit_status < 0 {
+        (None, None)
+    } else if exit_status > 256 {
+        (None, Some(exit_status - 256))
+    } else {
+        (Some(exit_status), None)
+    }
+}
+
+fn run_pbs_command(program: &str, args: &[&str], job_no: u32) -> Result<(), String> {
+    let ret = match Command::new(program)
+                            .args(args)
+                            .arg(job_no.to_string())
+                            .status() {
+        Ok(ret) => ret,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    match ret.success() {
+        true => Ok(()),
+        false => Err(format!("{} failed for PBS job {}", program, job_no)),
+    }
+}
+
+fn resource_directives(resources: &PbsResources) -> String {
+    let mut directives = String::new();
+
+    if let Some(walltime) = resources.walltime {
+        directives += &format!("#PBS -l walltime={}\n", job_runner::format_hms(walltime));
+    }
+
+    let mut chunk = String::from("select=1");
+    if let Some(ncpus) = resources.ncpus {
+        chunk += &format!(":ncpus={}", ncpus);
+    }
+    if let Some(mem) = resources.mem {
+        chunk += &format!(":mem={}b", mem);
+    }
+    if resources.ncpus.is_some() || resources.mem.is_some() {
+        directives += &format!("#PBS -l {}\n", chunk);
+    }
+
+    directives
 }
 
 impl job_runner::JobRunner for PbsJobRunner {
@@ -29,25 +91,69 @@ impl job_runner::JobRunner for PbsJobRunner {
             return Ok(mmb::State::NotStarted);
         }
 
-        match pbs::get_job_info(self.job_no.unwrap()) {
-            Ok(info) => match info.state {
-                pbs::JobState::Queued => Ok(mmb::State::Queued),
-                pbs::JobState::Held => Ok(mmb::State::Failed),
-                pbs::JobState::Running | pbs::JobState::Exiting => Ok(mmb::State::Running),
-                pbs::JobState::Finished => Ok(mmb::State::Finished),
-                pbs::JobState::Unknown => Ok(mmb::State::Unknown), /* Unknown state can mean that the job has already finished and been removed from the queue log */
+        match pbs::job_info(self.job_no.unwrap()) {
+            Ok(info) => {
+                if !info.exec_node.is_empty() {
+                    self.exec_node = Some(info.exec_node.clone());
+                }
+                let state = info.state;
+                let exit_status = info.exit_status;
+                self.last_info = Some(info);
+
+                match state {
+                    pbs::JobState::Queued | pbs::JobState::Running | pbs::JobState::Exiting | pbs::JobState::Held | pbs::JobState::Suspended if self.stopping => Ok(mmb::State::Stopping),
+                    pbs::JobState::Finished if self.stopping => Ok(mmb::State::Cancelled),
+                    pbs::JobState::Queued => Ok(mmb::State::Queued),
+                    pbs::JobState::Held => Ok(mmb::State::Held),
+                    pbs::JobState::Suspended => Ok(mmb::State::Suspended),
+                    pbs::JobState::Running | pbs::JobState::Exiting => Ok(mmb::State::Running),
+                    pbs::JobState::Finished => match exit_status {
+                        Some(0) | None => Ok(mmb::State::Finished),
+                        Some(_) => Ok(mmb::State::Failed),
+                    },
+                    pbs::JobState::Unknown => Ok(mmb::State::Unknown), /* Unknown state can mean that the job has already finished and been removed from the queue log */
+                }
             },
             Err(e) => Err(e),
         }
     }
 
+    fn failure_reason(&mut self) -> Option<job_runner::FailureReason> {
+        let exit_status = match &self.last_info {
+            Some(pbs::JobInfo{ state: pbs::JobState::Finished, exit_status: Some(n), .. }) => *n,
+            _ => return None,
+        };
+        if exit_status == 0 {
+            return None;
+        }
+        if exit_status < 0 {
+            return Some(job_runner::FailureReason::ExecutionFailed);
+        }
+
+        let killed_for = match &self.job_dir {
+            Some(job_dir) => read_kill_reason(&job_runner::mk_stderr_file_path(job_dir.clone())),
+            None => None,
+        };
+        match killed_for {
+            Some(reason) => Some(reason),
+            None => match split_exit_status(exit_status) {
+                (_, Some(_)) => Some(job_runner::FailureReason::KilledBySignal),
+                _ => Some(job_runner::FailureReason::NonZeroExitStatus),
+            },
+        }
+    }
+
+    fn manifest(&self) -> job_runner::RunnerManifest {
+        job_runner::RunnerManifest::Pbs { job_no: self.job_no, queue: self.queue.clone() }
+    }
+
     fn prune_job_dir(&self, job_dir: PathBuf) -> Result<(), String> {
-        let stderr_file_path = mk_stderr_file_path(job_dir.clone());
+        let stderr_file_path = job_runner::mk_stderr_file_path(job_dir.clone());
         if let Err(e) = std::fs::remove_file(&stderr_file_path) {
             log_plain!(Error, LOGSRC, &format!("Cannot delete strerr output file {}: {}", stderr_file_path.to_str().unwrap_or(logging::INV_FILE_PATH), e.to_string()));
         }
 
-        let stdout_file_path = mk_stdout_file_path(job_dir.clone());
+        let stdout_file_path = job_runner::mk_stdout_file_path(job_dir.clone());
         if let Err(e) = std::fs::remove_file(&stdout_file_path) {
             log_plain!(Error, LOGSRC, &format!("Cannot delete stdout output file {}: {}", stdout_file_path.to_str().unwrap_or(logging::INV_FILE_PATH), e.to_string()));
         }
@@ -55,22 +161,61 @@ impl job_runner::JobRunner for PbsJobRunner {
         Ok(())
     }
 
+    fn resume(&mut self) -> Result<(), String> {
+        let job_no = match self.job_no {
+            Some(no) => no,
+            None => return Err(String::from("Job has not been submitted")),
+        };
+
+        match self.last_info.as_ref().map(|info| info.state) {
+            Some(pbs::JobState::Held) => run_pbs_command("qrls", &[], job_no),
+            Some(pbs::JobState::Suspended) => run_pbs_command("qsig", &["-s", "resume"], job_no),
+            _ => Err(String::from("Job is not suspended")),
+        }
+    }
+
+    fn run_summary(&mut self) -> Option<job_runner::RunSummary> {
+        match &self.last_info {
+            Some(info) if info.state == pbs::JobState::Finished => {
+                let (exit_status, signal) = match info.exit_status {
+                    Some(n) => split_exit_status(n),
+                    None => (None, None),
+                };
+                Some(job_runner::RunSummary{
+                    exit_status,
+                    signal,
+                    exec_host: self.exec_node.clone(),
+                    cpu_time: info.cpu_time.clone(),
+                    walltime: info.walltime.clone(),
+                })
+            },
+            _ => None,
+        }
+    }
+
     fn start(&mut self, job_dir: PathBuf, cmds_file_path: &Path, diag_file_path: &Path, progress_file_path: &Path) -> Result<(), String> {
         let starter_file_path = match self.write_starter_file(job_dir.clone(), cmds_file_path, diag_file_path, progress_file_path) {
             Ok(path) => path,
             Err(e) => return Err(e),
         };
-        let stdout_file = mk_stdout_file_path(job_dir.clone());
-        let stderr_file = mk_stderr_file_path(job_dir.clone());
+        let stdout_file = job_runner::mk_stdout_file_path(job_dir.clone());
+        let stderr_file = job_runner::mk_stderr_file_path(job_dir.clone());
+
+        let mut args = Vec::<&OsStr>::new();
+        if let Some(queue) = &self.queue {
+            args.push(OsStr::new("-q"));
+            args.push(OsStr::new(queue.as_str()));
+        }
+        args.extend_from_slice(&[
+            OsStr::new("-o"),
+            stdout_file.as_os_str(),
+            OsStr::new("-e"),
+            stderr_file.as_os_str(),
+            starter_file_path.as_os_str()
+        ]);
 
         let cmdout = match Command::new("qsub")
-                                   .args(&[
-                                       OsStr::new("-o"),
-                                       stdout_file.as_os_str(),
-                                       OsStr::new("-e"),
-                                       stderr_file.as_os_str(),
-                                       starter_file_path.as_os_str()
-                                   ])
+                                   .args(&args)
                                    .current_dir(job_dir.as_path())
                                    .output() {
             Ok(cmdout) => cmdout,
@@ -99,12 +244,10 @@ impl job_runner::JobRunner for PbsJobRunner {
             },
         };
 
-        match pbs::get_job_info(self.job_no.unwrap()) {
-            Ok(info) => {
-                self.exec_node = Some(info.exec_node);
-            },
-            Err(e) => return Err(e),
-        };
+        self.exec_node = None;
+        self.last_info = None;
+        self.job_dir = Some(job_dir);
+        pbs::track(self.job_no.unwrap(), true);
 
         Ok(())
     }
@@ -122,23 +265,70 @@ impl job_runner::JobRunner for PbsJobRunner {
         };
 
         match ret.success() {
-            true => Ok(()),
+            true => {
+                /* PBS takes a while to terminate the job, it is reported as stopping until then */
+                self.stopping = true;
+                Ok(())
+            },
             false => Err(String::from("Failed to remove job from queue")),
         }
     }
+
+    fn suspend(&mut self) -> Result<(), String> {
+        let job_no = match self.job_no {
+            Some(no) => no,
+            None => return Err(String::from("Job has not been submitted")),
+        };
+
+        /* Job that is still waiting in the queue is held, running job is suspended */
+        match self.last_info.as_ref().map(|info| info.state) {
+            Some(pbs::JobState::Queued) => run_pbs_command("qhold", &[], job_no),
+            Some(pbs::JobState::Running) => run_pbs_command("qsig", &["-s", "suspend"], job_no),
+            _ => Err(String::from("Job is not running")),
+        }
+    }
 }
 
 pub struct PbsJobRunner {
     job_no: Option<u32>,
     exec_node: Option<String>,
+    queue: Option<String>,
+    resources: PbsResources,
+    job_dir: Option<PathBuf>,
+    last_info: Option<pbs::JobInfo>,
+    stopping: bool,
 }
 
 impl PbsJobRunner {
-    pub fn create() -> Result<PbsJobRunner, String> {
+    pub fn create(queue: Option<String>, resources: PbsResources) -> Result<PbsJobRunner, String> {
         Ok(
             PbsJobRunner{
                 job_no: None,
                 exec_node: None,
+                queue,
+                resources,
+                job_dir: None,
+                last_info: None,
+                stopping: false,
+            }
+        )
+    }
+
+    pub fn restore(job_no: Option<u32>, queue: Option<String>, job_dir: &Path) -> Result<PbsJobRunner, String> {
+        if let Some(no) = job_no {
+            pbs::track(no, false);
+        }
+
+        Ok(
+            PbsJobRunner{
+                job_no,
+                exec_node: None,
+                queue,
+                /* Resources only matter when the job is submitted */
+                resources: PbsResources::default(),
+                job_dir: Some(job_dir.to_path_buf()),
+                last_info: None,
+                stopping: false,
             }
         )
     }
@@ -154,8 +344,10 @@ impl PbsJobRunner {
 
         let script = format!(
             "#!/bin/sh\n\
+             {}\
              cd \"$PBS_O_WORKDIR\" || exit 1\n\
              {} -C {} -output {} -progress {}",
+            resource_directives(&self.resources),
             config::get().mmb_exec_path,
             cmds_file_path.display(),
             diag_file_path.display(),
@@ -167,3 +359,25 @@ impl PbsJobRunner {
         }
     }
 }
+
+impl Drop for PbsJobRunner {
+    fn drop(&mut self) {
+        if let Some(no) = self.job_no {
+            pbs::untrack(no);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_status_is_split() {
+        assert_eq!(split_exit_status(0), (Some(0), None));
+        assert_eq!(split_exit_status(3), (Some(3), None));
+        assert_eq!(split_exit_status(256 + 9), (None, Some(9)));
+        /* JOB_EXEC_FAIL1 */
+        assert_eq!(split_exit_status(-1), (None, None));
+    }
+}
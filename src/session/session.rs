@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::fs::DirBuilder;
 use std::path::PathBuf;
 use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
 use crate::config;
@@ -10,19 +11,25 @@ use crate::log_plain;
 use crate::mmb;
 use crate::server::api;
 use crate::session;
-use crate::session::{job, JobError};
+use crate::session::{job, manifest, JobError};
 
 const LOGSRC: &'static str = "session";
 
 struct SessionData {
     jobs: HashMap<Uuid, job::Job>,
     is_logged_in: bool,
+    last_activity: SystemTime,
 }
 
 pub struct Session {
     data: RwLock<SessionData>,
     id: Uuid,
     jobs_dir: PathBuf,
+    created_on: SystemTime,
+}
+
+fn jobs_disk_usage(jobs: &HashMap<Uuid, job::Job>) -> u64 {
+    jobs.values().map(|job| job.disk_usage()).sum()
 }
 
 fn prepare_job_dir(root: &PathBuf, id: &Uuid) -> Result<PathBuf, String> {
@@ -62,20 +69,26 @@ impl Session {
                 let data = RwLock::new(SessionData{
                         jobs: HashMap::new(),
                         is_logged_in,
+                        last_activity: SystemTime::now(),
                     }
                 );
 
-                Ok(Session{
+                let session = Session{
                     data,
                     id,
                     jobs_dir,
-                })
+                    created_on: SystemTime::now(),
+                };
+                match session.save_manifest(is_logged_in) {
+                    Ok(()) => Ok(session),
+                    Err(e) => Err(format!("Cannot write session manifest: {}", e)),
+                }
             },
             Err(e) => Err(e.to_string()),
         }
     }
 
-    pub fn create_job(&self, name: String, synthetic_commands: Option<api::Commands>, raw_commands: Option<String>) -> Result<Uuid, JobError> {
+    pub fn create_job(&self, name: String, synthetic_commands: Option<api::Commands>, raw_commands: Option<String>, runner_profile: Option<String>) -> Result<Uuid, JobError> {
         assert!(!(synthetic_commands.is_some() && raw_commands.is_some()));
 
         if name.len() < 1 {
@@ -84,12 +97,17 @@ impl Session {
         if self.has_job_by_name(&name) {
             return Err(JobError::BadInput(format!("Job with name {} already exists", name)));
         }
+        if let Some(commands) = &synthetic_commands {
+            if let Err(e) = mmb::commands::validate(commands) {
+                return Err(JobError::BadInput(e));
+            }
+        }
 
         let id = Uuid::new_v4();
 
         match prepare_job_dir(&self.jobs_dir, &id) {
             Ok(job_dir) => {
-                match job::Job::create(name, job_dir, synthetic_commands, raw_commands) {
+                match job::Job::create(name, job_dir, synthetic_commands, raw_commands, runner_profile) {
                     Ok(job) => {
                         let mut data = self.data.write().unwrap();
                         data.jobs.insert(id, job);
@@ -115,14 +133,22 @@ impl Session {
 
         let id = Uuid::new_v4();
         let mut data = self.data.write().unwrap();
+        let session_usage = jobs_disk_usage(&data.jobs);
         let src_job = match data.jobs.get_mut(src_id) {
             Some(v) => v,
             None => return Err(JobError::BadInput(String::from("No job to clone"))),
         };
 
+        if let Some(quota) = config::get().session_quota {
+            let usage = session_usage + src_job.disk_usage();
+            if usage > quota {
+                return Err(JobError::BadInput(format!("Cloned job would exceed the disk quota of the session ({} of {} bytes)", usage, quota)));
+            }
+        }
+
         match src_job.info() {
             Ok(info) => {
-                if info.state == mmb::State::Running {
+                if info.state.is_active() {
                     return Err(JobError::BadInput(String::from("Running jobs cannot be cloned")));
                 }
             },
@@ -149,6 +175,24 @@ impl Session {
         }
     }
 
+    pub fn continue_job(&self, id: Uuid) -> Result<(), String> {
+        let mut data = self.data.write().unwrap();
+
+        match data.jobs.get_mut(&id) {
+            Some(job) => job.resume(),
+            None => Err(String::from("No such job")),
+        }
+    }
+
+    pub fn convert_job_to_synthetic(&self, id: &Uuid) -> Result<(), JobError> {
+        let mut data = self.data.write().unwrap();
+
+        match data.jobs.get_mut(id) {
+            Some(job) => job.convert_to_synthetic(),
+            None => Err(JobError::BadInput(format!("Job with id {} does not exist", id))),
+        }
+    }
+
     pub fn delete_job(&self, id: &Uuid) -> bool {
         let mut data = self.data.write().unwrap();
 
@@ -159,7 +203,7 @@ impl Session {
 
         match job.info() {
             Ok(info) => {
-                if info.state == mmb::State::Running {
+                if info.state.is_active() {
                     return false;
                 }
             },
@@ -169,7 +213,9 @@ impl Session {
             },
         };
 
-        data.jobs.remove(id);
+        if let Some(job) = data.jobs.remove(id) {
+            job.delete();
+        }
         return true;
     }
 
@@ -182,6 +228,100 @@ impl Session {
         }
     }
 
+    /* Deletes the data of a reclaimed session if none of its jobs is active anymore. Returns true if the data has been deleted */
+    pub fn delete_if_ended(&self) -> bool {
+        let mut data = self.data.write().unwrap();
+
+        for (id, job) in data.jobs.iter_mut() {
+            match job.info() {
+                Ok(info) => {
+                    if info.state.is_active() {
+                        return false;
+                    }
+                },
+                /* State that cannot be determined now is not likely to be determined later */
+                Err(e) => log_plain!(Warning, LOGSRC, &format!("Deleting job ID {} of reclaimed session whose state is unknown: {}", id, e)),
+            };
+        }
+
+        for (_, job) in data.jobs.drain() {
+            job.delete();
+        }
+
+        if let Err(e) = std::fs::remove_dir_all(&self.jobs_dir) {
+            log_plain!(Error, LOGSRC, &format!("Cannot delete session directory {}: {}", self.jobs_dir.to_str().unwrap_or(logging::INV_FILE_PATH), e));
+        }
+
+        true
+    }
+
+    /* Walks the session directory, quota checks use the cached usage of the jobs instead */
+    pub fn disk_usage(&self) -> Result<u64, String> {
+        session::disk_usage(&self.jobs_dir)
+    }
+
+    pub fn enforce_quotas(&self) {
+        let cfg = config::get();
+        if cfg.session_quota.is_none() && cfg.job_quota.is_none() {
+            return;
+        }
+
+        let jobs: Vec<(Uuid, PathBuf, u64)> = self.data.read().unwrap().jobs.iter()
+            .map(|(id, job)| (*id, job.dir(), job.uploaded_since_refresh()))
+            .collect();
+
+        /* Uploads only add to the cached usage, the actual usage is determined here.
+         * Walking the job directories may take a while so the session is not locked meanwhile */
+        let mut usages = Vec::new();
+        for (id, job_dir, uploaded) in jobs {
+            match session::disk_usage(&job_dir) {
+                Ok(usage) => usages.push((id, usage, uploaded)),
+                Err(e) => log_plain!(Error, LOGSRC, &format!("Cannot determine disk usage of job ID {}: {}", id, e)),
+            };
+        }
+
+        let mut data = self.data.write().unwrap();
+        for (id, usage, uploaded) in usages {
+            if let Some(job) = data.jobs.get_mut(&id) {
+                job.set_disk_usage(usage, uploaded);
+            }
+        }
+
+        let session_over_quota = cfg.session_quota.is_some_and(|quota| jobs_disk_usage(&data.jobs) > quota);
+
+        for (id, job) in data.jobs.iter_mut() {
+            match job.info() {
+                Ok(info) => {
+                    if info.state != mmb::State::Running {
+                        continue;
+                    }
+                },
+                Err(_) => continue,
+            };
+
+            let job_over_quota = cfg.job_quota.is_some_and(|quota| job.disk_usage() > quota);
+
+            if session_over_quota || job_over_quota {
+                log_plain!(Warning, LOGSRC, &format!("Stopping job ID {} of session {} because it exceeded its disk quota", id, self.id));
+                if let Err(e) = job.stop_over_quota() {
+                    log_plain!(Error, LOGSRC, &format!("Cannot stop job ID {}: {}", id, e));
+                }
+            }
+        }
+    }
+
+    pub fn enforce_timeouts(&self) {
+        let mut data = self.data.write().unwrap();
+
+        for (id, job) in data.jobs.iter_mut() {
+            match job.enforce_timeout() {
+                Ok(true) => log_plain!(Warning, LOGSRC, &format!("Stopped job ID {} of session {} because it exceeded its timeout", id, self.id)),
+                Ok(false) => {},
+                Err(e) => log_plain!(Error, LOGSRC, &format!("Cannot stop timed out job ID {}: {}", id, e)),
+            };
+        }
+    }
+
     pub fn finish_upload(&self, job_id: Uuid, transfer_id: Uuid) -> Result<(), String> {
         let mut data = self.data.write().unwrap();
 
@@ -210,9 +350,22 @@ impl Session {
         self.id.clone()
     }
 
+    pub fn idle_for(&self) -> Duration {
+        let data = self.data.read().unwrap();
+
+        SystemTime::now().duration_since(data.last_activity).unwrap_or(Duration::ZERO)
+    }
+
     pub fn init_upload(&self, id: &Uuid, file_name: String) -> Result<Uuid, String> {
         let mut data = self.data.write().unwrap();
 
+        if let Some(quota) = config::get().session_quota {
+            let usage = jobs_disk_usage(&data.jobs);
+            if usage >= quota {
+                return Err(format!("Disk quota of the session is exhausted ({} of {} bytes used)", usage, quota));
+            }
+        }
+
         match data.jobs.get_mut(id) {
             Some(job) => job.init_upload(file_name),
             None => Err(String::from("No such job"))
@@ -281,6 +434,12 @@ impl Session {
         }
     }
 
+    pub fn job_console_output(&self, id: &Uuid) -> Option<Result<job::ConsoleOutput, String>> {
+        let data = self.data.read().unwrap();
+
+        data.jobs.get(id).map(|job| job.console_output())
+    }
+
     pub fn job_diagnostics(&self, id: &Uuid) -> Option<Result<String, String>> {
         let mut data = self.data.write().unwrap();
 
@@ -317,6 +476,80 @@ impl Session {
         }
     }
 
+    /* Stops all jobs of an expired session. The session data is deleted by delete_if_ended()
+     * once the jobs have actually ended, jobs that are still being stopped may write into it. */
+    pub fn reclaim(&self) {
+        let mut data = self.data.write().unwrap();
+
+        data.is_logged_in = false;
+
+        for (id, job) in data.jobs.iter_mut() {
+            let is_active = match job.info() {
+                Ok(info) => info.state.is_active(),
+                Err(e) => {
+                    log_plain!(Error, LOGSRC, &format!("Cannot get info for job ID {} of reclaimed session: {}", id, e));
+                    true
+                },
+            };
+            if is_active {
+                if let Err(e) = job.abort() {
+                    log_plain!(Error, LOGSRC, &format!("Cannot stop job ID {} of reclaimed session: {}", id, e));
+                }
+            }
+        }
+    }
+
+    pub fn restore(id: Uuid, jobs_dir: PathBuf) -> Result<Session, String> {
+        let session_manifest = match manifest::read::<manifest::SessionManifest>(&manifest::mk_session_manifest_path(jobs_dir.clone())) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Cannot read session manifest: {}", e)),
+        };
+
+        let dir_lister = match std::fs::read_dir(&jobs_dir) {
+            Ok(v) => v,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let mut jobs = HashMap::new();
+        for entry in dir_lister {
+            let path = match entry {
+                Ok(v) => v.path(),
+                Err(e) => return Err(e.to_string()),
+            };
+            if !path.is_dir() {
+                continue;
+            }
+
+            let job_id = match path.file_name().and_then(|name| name.to_str()).and_then(|name| session::str_to_uuid(name).ok()) {
+                Some(v) => v,
+                None => {
+                    log_plain!(Warning, LOGSRC, &format!("Directory {} does not look like a job directory, skipping", path.to_str().unwrap_or(logging::INV_FILE_PATH)));
+                    continue;
+                },
+            };
+
+            match job::Job::restore(path.clone()) {
+                Ok(job) => {
+                    jobs.insert(job_id, job);
+                },
+                Err(e) => log_plain!(Error, LOGSRC, &format!("Cannot restore job {} of session {}: {}", job_id, id, e)),
+            };
+        }
+
+        log_plain!(Info, LOGSRC, &format!("Restored session {} with {} job(s)", id, jobs.len()));
+
+        Ok(Session{
+            data: RwLock::new(SessionData{
+                jobs,
+                is_logged_in: session_manifest.is_logged_in,
+                last_activity: SystemTime::now(),
+            }),
+            id,
+            jobs_dir,
+            created_on: session_manifest.created_on,
+        })
+    }
+
     pub fn retire_ended_jobs(&self) {
         let mut data = self.data.write().unwrap();
         for (id, job) in data.jobs.iter_mut() {
@@ -326,30 +559,46 @@ impl Session {
         }
     }
 
+    pub fn session_age(&self) -> Duration {
+        SystemTime::now().duration_since(self.created_on).unwrap_or(Duration::ZERO)
+    }
+
+    pub fn resume_job(&self, id: &Uuid, commands: api::Commands, pbs_resources: Option<api::PbsResources>, timeout: Option<u64>) -> Result<(), JobError> {
+        let mut data = self.data.write().unwrap();
+
+        match data.jobs.get_mut(id) {
+            Some(job) => job.resume_from_stage(commands, pbs_resources, timeout),
+            None => Err(JobError::BadInput(format!("Job with id {} does not exist", id))),
+        }
+    }
+
     pub fn set_login_state(&self, login_state: bool) {
         let mut data = self.data.write().unwrap();
 
         data.is_logged_in = login_state;
+        if let Err(e) = self.save_manifest(login_state) {
+            log_plain!(Error, LOGSRC, &format!("Failed to update manifest of session {}: {}", self.id, e));
+        }
     }
 
-    pub fn start_job(&self, id: &Uuid, commands: api::Commands) -> Result<(), JobError> {
+    pub fn start_job(&self, id: &Uuid, commands: api::Commands, runner_profile: Option<String>, pbs_resources: Option<api::PbsResources>, timeout: Option<u64>) -> Result<(), JobError> {
         if !self.has_job(id) {
             return Err(JobError::BadInput(format!("Job with id {} does not exist", id)));
         }
 
         let mut data = self.data.write().unwrap();
         let job = data.jobs.get_mut(&id).unwrap();
-        job.start(commands)
+        job.start(commands, runner_profile, pbs_resources, timeout)
     }
 
-    pub fn start_job_raw(&self, id: &Uuid, raw_commands: String) -> Result<(), JobError> {
+    pub fn start_job_raw(&self, id: &Uuid, raw_commands: String, runner_profile: Option<String>, pbs_resources: Option<api::PbsResources>, timeout: Option<u64>) -> Result<(), JobError> {
         if !self.has_job(id) {
             return Err(JobError::BadInput(format!("Job with id {} does not exist", id)));
         }
 
         let mut data = self.data.write().unwrap();
         let job = data.jobs.get_mut(&id).unwrap();
-        job.start_raw(raw_commands)
+        job.start_raw(raw_commands, runner_profile, pbs_resources, timeout)
     }
 
     pub fn stop_job(&self, id: Uuid) -> Result<(), String> {
@@ -361,6 +610,15 @@ impl Session {
         }
     }
 
+    pub fn suspend_job(&self, id: Uuid) -> Result<(), String> {
+        let mut data = self.data.write().unwrap();
+
+        match data.jobs.get_mut(&id) {
+            Some(job) => job.suspend(),
+            None => Err(String::from("No such job")),
+        }
+    }
+
     pub fn terminate_hung_uploads(&self) {
         let mut data = self.data.write().unwrap();
 
@@ -370,12 +628,37 @@ impl Session {
         }
     }
 
+    pub fn touch(&self) {
+        let mut data = self.data.write().unwrap();
+
+        data.last_activity = SystemTime::now();
+    }
+
     pub fn upload_chunk(&self, job_id: &Uuid, transfer_id: &Uuid, index: u32, chunk: Vec<u8>) -> Result<(), String> {
         let mut data = self.data.write().unwrap();
 
-        match data.jobs.get_mut(job_id) {
-            Some(job) => job.upload_chunk(transfer_id, index, chunk),
+        let usage = jobs_disk_usage(&data.jobs);
+        let job = match data.jobs.get_mut(job_id) {
+            Some(job) => job,
             None => return Err(String::from("No such job")),
+        };
+
+        if let Some(quota) = config::get().session_quota {
+            if usage + chunk.len() as u64 > quota {
+                job.cancel_upload(transfer_id)?;
+                return Err(format!("Upload was cancelled because it would exceed the disk quota of the session ({} of {} bytes used)", usage, quota));
+            }
         }
+
+        job.upload_chunk(transfer_id, index, chunk)
+    }
+
+    fn save_manifest(&self, is_logged_in: bool) -> Result<(), String> {
+        let session_manifest = manifest::SessionManifest{
+            created_on: self.created_on,
+            is_logged_in,
+        };
+
+        manifest::write(&manifest::mk_session_manifest_path(self.jobs_dir.clone()), &session_manifest)
     }
 }
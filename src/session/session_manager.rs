@@ -1,19 +1,99 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use std::thread;
 use uuid::Uuid;
 
 use crate::config;
+use crate::logging;
+use crate::log_plain;
 use crate::session;
 use crate::session::session::Session;
 
+const LOGSRC: &str = "session_manager";
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+const ENFORCE_INTERVAL: Duration = Duration::from_secs(10);
+
 pub struct SessionManager {
     sessions: HashMap<Uuid, Arc<Session>>,
     session_watchdogs: HashMap<Uuid, thread::JoinHandle<()>>,
 }
 
+fn is_expired(session: &Session, idle_ttl: Option<u64>, max_age: Option<u64>) -> bool {
+    if let Some(ttl) = idle_ttl {
+        if session.idle_for() > Duration::from_secs(ttl) {
+            return true;
+        }
+    }
+    if let Some(age) = max_age {
+        if session.session_age() > Duration::from_secs(age) {
+            return true;
+        }
+    }
+
+    false
+}
+
+/* Limits apply to jobs of all sessions, including sessions that are logged out
+ * and have no watchdog */
+pub fn spawn_enforcer(sm: Arc<RwLock<SessionManager>>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(ENFORCE_INTERVAL);
+
+            let sessions: Vec<Arc<Session>> = sm.read().unwrap().sessions.values().cloned().collect();
+            for session in sessions.iter() {
+                session.enforce_quotas();
+                session.enforce_timeouts();
+            }
+        }
+    })
+}
+
+pub fn spawn_sweeper(sm: Arc<RwLock<SessionManager>>) -> Option<thread::JoinHandle<()>> {
+    let cfg = config::get();
+    if cfg.session_idle_ttl.is_none() && cfg.session_max_age.is_none() {
+        log_plain!(Info, LOGSRC, "No session lifetime limits are configured, sessions will never expire");
+        return None;
+    }
+
+    Some(thread::spawn(move || {
+        /* Reclaimed sessions whose jobs are still being stopped */
+        let mut reclaiming: Vec<Arc<Session>> = Vec::new();
+
+        loop {
+            thread::sleep(SWEEP_INTERVAL);
+
+            /* Expired sessions are only taken out of the manager while it is locked.
+             * Reclaiming them may take a while so it is done without holding the lock */
+            let expired = sm.write().unwrap().take_expired_sessions();
+            for session in expired.iter() {
+                log_plain!(Info, LOGSRC, &format!("Reclaiming expired session {}", session.id()));
+                session.reclaim();
+            }
+            let reclaimed = expired.len();
+            reclaiming.extend(expired);
+            reclaiming.retain(|session| !session.delete_if_ended());
+
+            let remaining = sm.read().unwrap().sessions.len();
+            log_plain!(Info, LOGSRC, &format!("Session sweep finished, {} session(s) reclaimed, {} session(s) waiting for their jobs to stop, {} session(s) remaining", reclaimed, reclaiming.len(), remaining));
+        }
+    }))
+}
+
+fn spawn_watchdog(session_handle: Arc<Session>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        while session_handle.is_logged_in() {
+            thread::sleep(Duration::new(10, 0));
+            session_handle.terminate_hung_uploads();
+            session_handle.retire_ended_jobs();
+        }
+
+        println!("Session watchdog exited");
+    })
+}
+
 impl<'a> SessionManager {
     pub fn create() -> SessionManager {
         SessionManager {
@@ -39,18 +119,7 @@ impl<'a> SessionManager {
                     Ok(s) => {
                         let session_handle = Arc::from(s);
                         self.sessions.insert(*session_id, session_handle.clone());
-                        self.session_watchdogs.insert(
-                            *session_id,
-                            thread::spawn(move || {
-                                while session_handle.is_logged_in() {
-                                    thread::sleep(Duration::new(10, 0));
-                                    session_handle.terminate_hung_uploads();
-                                    session_handle.retire_ended_jobs();
-                                }
-
-                                println!("Session watchdog exited");
-                            })
-                        );
+                        self.session_watchdogs.insert(*session_id, spawn_watchdog(session_handle));
 
                         Ok(())
                     },
@@ -77,4 +146,68 @@ impl<'a> SessionManager {
             None => None,
         }
     }
+
+    pub fn restore_sessions(&mut self) {
+        let root = PathBuf::from(config::get().jobs_dir.clone());
+        let dir_lister = match std::fs::read_dir(&root) {
+            Ok(v) => v,
+            Err(e) => {
+                log_plain!(Error, LOGSRC, &format!("Cannot list jobs directory to restore sessions: {}", e));
+                return;
+            },
+        };
+
+        for entry in dir_lister {
+            let path = match entry {
+                Ok(v) => v.path(),
+                Err(e) => {
+                    log_plain!(Error, LOGSRC, &format!("Cannot read jobs directory entry: {}", e));
+                    continue;
+                },
+            };
+            if !path.is_dir() {
+                continue;
+            }
+
+            let session_id = match path.file_name().and_then(|name| name.to_str()).and_then(|name| session::str_to_uuid(name).ok()) {
+                Some(v) => v,
+                None => {
+                    log_plain!(Warning, LOGSRC, &format!("Directory {} does not look like a session directory, skipping", path.to_str().unwrap_or(logging::INV_FILE_PATH)));
+                    continue;
+                },
+            };
+
+            match Session::restore(session_id, path) {
+                Ok(s) => {
+                    let session_handle = Arc::from(s);
+                    self.sessions.insert(session_id, session_handle.clone());
+                    if session_handle.is_logged_in() {
+                        self.session_watchdogs.insert(session_id, spawn_watchdog(session_handle));
+                    }
+                },
+                Err(e) => log_plain!(Error, LOGSRC, &format!("Cannot restore session {}: {}", session_id, e)),
+            };
+        }
+
+        log_plain!(Info, LOGSRC, &format!("Restored {} session(s)", self.sessions.len()));
+    }
+
+    pub fn take_expired_sessions(&mut self) -> Vec<Arc<Session>> {
+        let cfg = config::get();
+
+        let expired_ids: Vec<Uuid> = self.sessions.iter()
+            .filter(|(_, s)| is_expired(s, cfg.session_idle_ttl, cfg.session_max_age))
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut expired = Vec::new();
+        for id in expired_ids {
+            self.session_watchdogs.remove(&id);
+            if let Some(s) = self.sessions.remove(&id) {
+                expired.push(s);
+            }
+        }
+
+        expired
+    }
 }
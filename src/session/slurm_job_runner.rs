@@ -0,0 +1,351 @@
+use std::ffi::OsStr;
+use std::io::Write;
+use std::fs::File;
+use std::process::Command;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::logging;
+use crate::log_plain;
+use crate::mmb;
+use crate::slurm;
+use super::job_runner;
+
+const LOGSRC: &str = "slurm_job_runner";
+
+fn run_scontrol(action: &str, job_id: u32) -> Result<(), String> {
+    let ret = match Command::new("scontrol")
+                            .args([action, &job_id.to_string()])
+                            .status() {
+        Ok(ret) => ret,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    match ret.success() {
+        true => Ok(()),
+        false => Err(format!("scontrol {} failed for Slurm job {}", action, job_id)),
+    }
+}
+
+impl job_runner::JobRunner for SlurmJobRunner {
+    fn executor_state(&mut self) -> Result<mmb::State, String> {
+        if self.job_id.is_none() {
+            return Ok(mmb::State::NotStarted);
+        }
+
+        match slurm::get_job_info(self.job_id.unwrap()) {
+            Ok(info) => {
+                if !info.exec_node.is_empty() {
+                    self.exec_node = Some(info.exec_node);
+                }
+                self.time_limit_exceeded = matches!(info.state, slurm::JobState::Timeout);
+
+                match info.state {
+                    slurm::JobState::Pending | slurm::JobState::Running | slurm::JobState::Suspended if self.stopping => Ok(mmb::State::Stopping),
+                    slurm::JobState::Pending if self.held => Ok(mmb::State::Suspended),
+                    slurm::JobState::Pending => Ok(mmb::State::Queued),
+                    slurm::JobState::Running => Ok(mmb::State::Running),
+                    slurm::JobState::Suspended => Ok(mmb::State::Suspended),
+                    slurm::JobState::Completed => Ok(mmb::State::Finished),
+                    slurm::JobState::Cancelled => Ok(mmb::State::Cancelled),
+                    slurm::JobState::Failed | slurm::JobState::Timeout => Ok(mmb::State::Failed),
+                    slurm::JobState::Unknown => Ok(mmb::State::Unknown),
+                }
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    fn failure_reason(&mut self) -> Option<job_runner::FailureReason> {
+        /* Limits of cluster jobs are enforced by the job management system */
+        match self.time_limit_exceeded {
+            true => Some(job_runner::FailureReason::WalltimeLimitExceeded),
+            false => None,
+        }
+    }
+
+    fn manifest(&self) -> job_runner::RunnerManifest {
+        job_runner::RunnerManifest::Slurm { job_id: self.job_id, partition: self.partition.clone(), held: self.held }
+    }
+
+    fn prune_job_dir(&self, job_dir: PathBuf) -> Result<(), String> {
+        let stderr_file_path = job_runner::mk_stderr_file_path(job_dir.clone());
+        if let Err(e) = std::fs::remove_file(&stderr_file_path) {
+            log_plain!(Error, LOGSRC, &format!("Cannot delete strerr output file {}: {}", stderr_file_path.to_str().unwrap_or(logging::INV_FILE_PATH), e));
+        }
+
+        let stdout_file_path = job_runner::mk_stdout_file_path(job_dir.clone());
+        if let Err(e) = std::fs::remove_file(&stdout_file_path) {
+            log_plain!(Error, LOGSRC, &format!("Cannot delete stdout output file {}: {}", stdout_file_path.to_str().unwrap_or(logging::INV_FILE_PATH), e));
+        }
+
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), String> {
+        let job_id = match self.job_id {
+            Some(id) => id,
+            None => return Err(String::from("Job has not been submitted")),
+        };
+
+        /* Only a hold placed by this server is released, holds placed by the user or the administrator are left alone */
+        if !self.held {
+            return Err(String::from("Job is not suspended"));
+        }
+        run_scontrol("release", job_id)?;
+        self.held = false;
+        Ok(())
+    }
+
+    fn run_summary(&mut self) -> Option<job_runner::RunSummary> {
+        None
+    }
+
+    fn start(&mut self, job_dir: PathBuf, cmds_file_path: &Path, diag_file_path: &Path, progress_file_path: &Path) -> Result<(), String> {
+        let starter_file_path = self.write_starter_file(job_dir.clone(), cmds_file_path, diag_file_path, progress_file_path)?;
+        let stdout_file = job_runner::mk_stdout_file_path(job_dir.clone());
+        let stderr_file = job_runner::mk_stderr_file_path(job_dir.clone());
+
+        let mut args = vec![
+            OsStr::new("--parsable"),
+            OsStr::new("--chdir"),
+            job_dir.as_os_str(),
+        ];
+        if let Some(partition) = &self.partition {
+            args.push(OsStr::new("--partition"));
+            args.push(OsStr::new(partition.as_str()));
+        }
+        let time_limit = self.time_limit.map(job_runner::format_hms);
+        if let Some(time_limit) = &time_limit {
+            args.push(OsStr::new("--time"));
+            args.push(OsStr::new(time_limit.as_str()));
+        }
+        args.extend_from_slice(&[
+            OsStr::new("-o"),
+            stdout_file.as_os_str(),
+            OsStr::new("-e"),
+            stderr_file.as_os_str(),
+            starter_file_path.as_os_str()
+        ]);
+
+        let cmdout = match Command::new("sbatch")
+                                   .args(&args)
+                                   .current_dir(job_dir.as_path())
+                                   .output() {
+            Ok(cmdout) => cmdout,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        if !cmdout.status.success() {
+            log_plain!(Error, LOGSRC, &format!("sbatch failed: {}", String::from_utf8_lossy(&cmdout.stderr)));
+            return Err(String::from("Failed to enqueue job"));
+        }
+
+        let stdout = match String::from_utf8(cmdout.stdout) {
+            Ok(stdout) => stdout,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        /* With --parsable sbatch prints "<job_id>" or "<job_id>;<cluster_name>" */
+        let job_id_str = stdout.trim().split(';').next().unwrap_or("");
+        self.job_id = match job_id_str.parse::<u32>() {
+            Ok(id) => Some(id),
+            Err(_) => return Err(format!("Invalid Slurm job ID {}", job_id_str)),
+        };
+        self.exec_node = None;
+        self.held = false;
+        self.time_limit_exceeded = false;
+
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        if self.job_id.is_none() {
+            return Ok(());
+        }
+
+        let ret = match Command::new("scancel")
+                                .args(&[self.job_id.unwrap().to_string()])
+                                .status() {
+            Ok(ret) => ret,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        match ret.success() {
+            true => {
+                /* Slurm takes a while to terminate the job, it is reported as stopping until then */
+                self.stopping = true;
+                Ok(())
+            },
+            false => Err(String::from("Failed to cancel job")),
+        }
+    }
+
+    fn suspend(&mut self) -> Result<(), String> {
+        let job_id = match self.job_id {
+            Some(id) => id,
+            None => return Err(String::from("Job has not been submitted")),
+        };
+
+        /* Suspending a running job takes operator privileges in Slurm, ordinary users can only hold pending jobs */
+        match slurm::get_job_info(job_id)?.state {
+            slurm::JobState::Pending if self.held => Err(String::from("Job is already suspended")),
+            slurm::JobState::Pending => {
+                run_scontrol("hold", job_id)?;
+                self.held = true;
+                Ok(())
+            },
+            slurm::JobState::Running => Err(String::from("Running Slurm jobs cannot be suspended, only jobs that are waiting in the queue can")),
+            _ => Err(String::from("Job is not running")),
+        }
+    }
+}
+
+pub struct SlurmJobRunner {
+    job_id: Option<u32>,
+    exec_node: Option<String>,
+    partition: Option<String>,
+    stopping: bool,
+    /* Job has been held by this server */
+    held: bool,
+    /* Time limit in seconds requested for the job when it is submitted */
+    time_limit: Option<u64>,
+    time_limit_exceeded: bool,
+}
+
+impl SlurmJobRunner {
+    pub fn create(partition: Option<String>, time_limit: Option<u64>) -> Result<SlurmJobRunner, String> {
+        Ok(
+            SlurmJobRunner{
+                job_id: None,
+                exec_node: None,
+                partition,
+                stopping: false,
+                held: false,
+                time_limit,
+                time_limit_exceeded: false,
+            }
+        )
+    }
+
+    pub fn restore(job_id: Option<u32>, partition: Option<String>, held: bool) -> Result<SlurmJobRunner, String> {
+        Ok(
+            SlurmJobRunner{
+                job_id,
+                exec_node: None,
+                partition,
+                stopping: false,
+                held,
+                time_limit: None,
+                time_limit_exceeded: false,
+            }
+        )
+    }
+
+    fn write_starter_file(&self, job_dir: PathBuf, cmds_file_path: &Path, diag_file_path: &Path, progress_file_path: &Path) -> Result<PathBuf, String> {
+        let mut starter_path = job_dir.clone();
+        starter_path.push("starter.sh");
+
+        let mut fh = match File::create(starter_path.clone()) {
+            Ok(fh) => fh,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let script = format!(
+            "#!/bin/sh\n\
+             {} -C {} -output {} -progress {}\n",
+            config::get().mmb_exec_path,
+            cmds_file_path.display(),
+            diag_file_path.display(),
+            progress_file_path.display()
+        );
+        match fh.write_all(script.as_bytes()) {
+            Ok(_) => Ok(starter_path),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use job_runner::{FailureReason, JobRunner};
+
+    /* Stubs of the Slurm tools print what the files next to them contain and record how they were called */
+    const STUBS: [(&str, &str); 5] = [
+        ("sbatch", "echo \"$@\" > \"$DIR/sbatch_args\"\necho \"1234;cluster\"\n"),
+        ("squeue", "if [ -f \"$DIR/queue\" ]; then cat \"$DIR/queue\"; fi\n"),
+        ("sacct", "cat \"$DIR/accounting\"\n"),
+        ("scontrol", "echo \"$@\" >> \"$DIR/scontrol_calls\"\n"),
+        ("scancel", "echo \"$@\" > \"$DIR/scancel_args\"\n"),
+    ];
+
+    fn install_stubs(dir: &Path) {
+        std::fs::create_dir_all(dir).unwrap();
+        for (name, body) in STUBS.iter() {
+            let path = dir.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\nDIR=$(dirname \"$0\")\n{}", body)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let path = std::env::var("PATH").unwrap_or_default();
+        std::env::set_var("PATH", format!("{}:{}", dir.display(), path));
+    }
+
+    fn read(dir: &Path, name: &str) -> String {
+        std::fs::read_to_string(dir.join(name)).unwrap()
+    }
+
+    #[test]
+    fn job_is_driven_through_slurm_tools() {
+        let dir = std::env::temp_dir().join(format!("slurm_job_runner_{}", std::process::id()));
+        let job_dir = dir.join("job");
+        std::fs::create_dir_all(&job_dir).unwrap();
+        install_stubs(&dir.join("bin"));
+        let bin = dir.join("bin");
+        let set = |name: &str, content: &str| std::fs::write(bin.join(name), content).unwrap();
+
+        let mut runner = SlurmJobRunner::create(Some(String::from("short")), Some(3723)).unwrap();
+        assert_eq!(runner.executor_state(), Ok(mmb::State::NotStarted));
+
+        runner.start(job_dir.clone(), &job_dir.join("commands.txt"), &job_dir.join("diag"), &job_dir.join("progress")).unwrap();
+        assert_eq!(runner.job_id, Some(1234));
+        let args = read(&bin, "sbatch_args");
+        assert!(args.contains("--partition short --time 01:02:03"), "{}", args);
+        assert!(args.trim_end().ends_with("starter.sh"));
+        assert!(job_dir.join("starter.sh").exists());
+
+        set("queue", "PENDING|None assigned\n");
+        assert_eq!(runner.executor_state(), Ok(mmb::State::Queued));
+
+        runner.suspend().unwrap();
+        assert_eq!(runner.executor_state(), Ok(mmb::State::Suspended));
+        assert!(matches!(runner.manifest(), job_runner::RunnerManifest::Slurm{ held: true, .. }));
+        runner.resume().unwrap();
+        assert_eq!(read(&bin, "scontrol_calls"), "hold 1234\nrelease 1234\n");
+        /* Hold that the server did not place is left alone */
+        assert!(runner.resume().is_err());
+
+        set("queue", "RUNNING|node01\n");
+        assert_eq!(runner.executor_state(), Ok(mmb::State::Running));
+        assert!(runner.suspend().is_err());
+
+        runner.stop().unwrap();
+        assert_eq!(read(&bin, "scancel_args").trim(), "1234");
+        assert_eq!(runner.executor_state(), Ok(mmb::State::Stopping));
+
+        /* Once the job leaves the queue its state comes from the accounting database */
+        std::fs::remove_file(bin.join("queue")).unwrap();
+        set("accounting", "CANCELLED by 1000|node01\n");
+        assert_eq!(runner.executor_state(), Ok(mmb::State::Cancelled));
+        assert_eq!(runner.exec_node.as_deref(), Some("node01"));
+        assert_eq!(runner.failure_reason(), None);
+
+        set("accounting", "TIMEOUT|node01\n");
+        assert_eq!(runner.executor_state(), Ok(mmb::State::Failed));
+        assert_eq!(runner.failure_reason(), Some(FailureReason::WalltimeLimitExceeded));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,120 @@
+use std::process::Command;
+
+pub enum JobState {
+    Pending,
+    Running,
+    Suspended,
+    Completed,
+    Failed,
+    Cancelled,
+    Timeout,
+    Unknown,
+}
+
+pub struct JobInfo {
+    pub state: JobState,
+    pub exec_node: String,
+}
+
+/* States that newer Slurm versions may add are reported as unknown rather than failing the query */
+fn parse_job_state(s: &str) -> JobState {
+    /* sacct may append details to the state, e.g. "CANCELLED by 1000" */
+    let state = match s.split_whitespace().next() {
+        Some(v) => v,
+        None => return JobState::Unknown,
+    };
+
+    match state {
+        "PENDING" | "REQUEUED" | "REQUEUE_HOLD" | "REQUEUE_FED" | "CONFIGURING" | "RESV_DEL_HOLD" => JobState::Pending,
+        "RUNNING" | "COMPLETING" | "SIGNALING" | "STAGE_OUT" | "RESIZING" => JobState::Running,
+        "SUSPENDED" | "STOPPED" => JobState::Suspended,
+        "COMPLETED" => JobState::Completed,
+        "FAILED" | "NODE_FAIL" | "OUT_OF_MEMORY" | "BOOT_FAIL" | "DEADLINE" | "PREEMPTED" | "REVOKED" | "SPECIAL_EXIT" => JobState::Failed,
+        "CANCELLED" => JobState::Cancelled,
+        "TIMEOUT" => JobState::Timeout,
+        _ => JobState::Unknown,
+    }
+}
+
+/* Both squeue and sacct are asked to print "<state>|<node list>" lines */
+fn parse_job_info_line(line: &str) -> Result<JobInfo, String> {
+    let parts: Vec<&str> = line.trim().split('|').collect();
+    if parts.len() != 2 {
+        return Err(format!("Unexpected job info line {}", line));
+    }
+
+    let state = parse_job_state(parts[0]);
+    let exec_node = match parts[1] {
+        "" | "None assigned" | "(null)" => String::new(),
+        node => String::from(node),
+    };
+
+    Ok(JobInfo{ state, exec_node })
+}
+
+fn run_query(cmd: &str, args: &[&str]) -> Result<Option<String>, String> {
+    match Command::new(cmd)
+                  .args(args)
+                  .output() {
+        Ok(output) => {
+            if !output.status.success() {
+                return Err(format!("Failed to execute {}", cmd));
+            }
+
+            match String::from_utf8(output.stdout) {
+                Ok(raw) => Ok(raw.lines().find(|l| !l.trim().is_empty()).map(String::from)),
+                Err(_) => Err(format!("{} output is not a valid string", cmd)),
+            }
+        },
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub fn get_job_info(job_id: u32) -> Result<JobInfo, String> {
+    let id = job_id.to_string();
+
+    /* squeue knows only about jobs that are still in the queue.
+     * Jobs that have already left it have to be looked up in the accounting database */
+    if let Ok(Some(line)) = run_query("squeue", &["-h", "-j", id.as_str(), "-o", "%T|%N"]) {
+        return parse_job_info_line(line.as_str());
+    }
+
+    match run_query("sacct", &["-n", "-X", "-P", "-j", id.as_str(), "-o", "State,NodeList"]) {
+        Ok(Some(line)) => parse_job_info_line(line.as_str()),
+        Ok(None) => Ok(JobInfo{ state: JobState::Unknown, exec_node: String::new() }),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_states_are_parsed() {
+        assert!(matches!(parse_job_state("PENDING"), JobState::Pending));
+        assert!(matches!(parse_job_state("REQUEUED"), JobState::Pending));
+        assert!(matches!(parse_job_state("COMPLETING"), JobState::Running));
+        assert!(matches!(parse_job_state("STOPPED"), JobState::Suspended));
+        assert!(matches!(parse_job_state("PREEMPTED"), JobState::Failed));
+        assert!(matches!(parse_job_state("NODE_FAIL"), JobState::Failed));
+        assert!(matches!(parse_job_state("OUT_OF_MEMORY"), JobState::Failed));
+        assert!(matches!(parse_job_state("CANCELLED by 1000"), JobState::Cancelled));
+        assert!(matches!(parse_job_state("TIMEOUT"), JobState::Timeout));
+        assert!(matches!(parse_job_state(""), JobState::Unknown));
+        assert!(matches!(parse_job_state("SOME_FUTURE_STATE"), JobState::Unknown));
+    }
+
+    #[test]
+    fn job_info_lines_are_parsed() {
+        let info = parse_job_info_line("RUNNING|node01\n").unwrap();
+        assert!(matches!(info.state, JobState::Running));
+        assert_eq!(info.exec_node, "node01");
+
+        let info = parse_job_info_line("PENDING|None assigned").unwrap();
+        assert!(matches!(info.state, JobState::Pending));
+        assert_eq!(info.exec_node, "");
+
+        assert!(parse_job_info_line("RUNNING").is_err());
+    }
+}